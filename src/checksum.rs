@@ -0,0 +1,112 @@
+// Without any checksum features the enums below are empty and their methods cannot be called.
+#![cfg_attr(
+    not(any(feature = "crc32", feature = "xxh3")),
+    allow(unused_imports, unused_variables, unreachable_code)
+)]
+
+use std::io::{self, Read, Write};
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+use crate::{ByteOrder, FileHeader, Result};
+
+/// A checksum of the uncompressed body of a file, which is verified once the body has been read. Each
+/// checksum requires the feature of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Checksum {
+    /// A 32 bit CRC, as used by gzip and zlib. Requires the `crc32` feature.
+    #[cfg(feature = "crc32")]
+    Crc32,
+    /// The 64 bit variant of xxHash3, which is faster to compute than a CRC. Requires the `xxh3` feature.
+    #[cfg(feature = "xxh3")]
+    Xxh3,
+}
+
+impl Checksum {
+    /// The header flag that marks a body ending with this checksum.
+    pub(crate) fn flag(self) -> u8 {
+        match self {
+            #[cfg(feature = "crc32")]
+            Self::Crc32 => FileHeader::CRC32_FLAG,
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3 => FileHeader::XXH3_FLAG,
+        }
+    }
+
+    /// The checksum marked by the header flags, failing if its feature is not enabled.
+    pub(crate) fn from_flags(flags: u8) -> Result<Option<Self>> {
+        if flags & FileHeader::CRC32_FLAG != 0 {
+            #[cfg(feature = "crc32")]
+            return Ok(Some(Self::Crc32));
+            #[cfg(not(feature = "crc32"))]
+            return Err(crate::Error::UnsupportedChecksum("crc32"));
+        }
+        if flags & FileHeader::XXH3_FLAG != 0 {
+            #[cfg(feature = "xxh3")]
+            return Ok(Some(Self::Xxh3));
+            #[cfg(not(feature = "xxh3"))]
+            return Err(crate::Error::UnsupportedChecksum("xxh3"));
+        }
+
+        Ok(None)
+    }
+
+    /// Writes a checksum value, a u32 for CRC32 and a u64 for xxHash3.
+    pub(crate) fn write_value<W: Write>(self, writer: &mut W, value: u64) -> io::Result<()> {
+        match self {
+            #[cfg(feature = "crc32")]
+            Self::Crc32 => writer.write_u32::<ByteOrder>(value as u32),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3 => writer.write_u64::<ByteOrder>(value),
+        }
+    }
+
+    pub(crate) fn read_value<R: Read>(self, reader: &mut R) -> io::Result<u64> {
+        match self {
+            #[cfg(feature = "crc32")]
+            Self::Crc32 => reader.read_u32::<ByteOrder>().map(u64::from),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3 => reader.read_u64::<ByteOrder>(),
+        }
+    }
+}
+
+/// Computes a checksum over the bytes it is given.
+pub(crate) enum Hasher {
+    #[cfg(feature = "crc32")]
+    Crc32(crc32fast::Hasher),
+    #[cfg(feature = "xxh3")]
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl Hasher {
+    pub(crate) fn new(checksum: Checksum) -> Self {
+        match checksum {
+            #[cfg(feature = "crc32")]
+            Checksum::Crc32 => Self::Crc32(crc32fast::Hasher::new()),
+            #[cfg(feature = "xxh3")]
+            Checksum::Xxh3 => Self::Xxh3(Box::default()),
+        }
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        // Matched by place so that the match is still exhaustive when no checksum features are enabled.
+        match *self {
+            #[cfg(feature = "crc32")]
+            Self::Crc32(ref mut hasher) => hasher.update(bytes),
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3(ref mut hasher) => hasher.update(bytes),
+        }
+    }
+
+    /// The checksum of the bytes so far.
+    pub(crate) fn value(&self) -> u64 {
+        match *self {
+            #[cfg(feature = "crc32")]
+            Self::Crc32(ref hasher) => hasher.clone().finalize() as u64,
+            #[cfg(feature = "xxh3")]
+            Self::Xxh3(ref hasher) => hasher.digest(),
+        }
+    }
+}
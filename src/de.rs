@@ -1,53 +1,161 @@
 use std::{
-    io::{Cursor, Read},
-    vec,
+    borrow::Cow,
+    fmt,
+    io::{self, BufRead},
+    marker::PhantomData,
 };
 
-use byteorder::ReadBytesExt;
-use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
-use peekread::{BufPeekReader, PeekRead};
-use serde::{de::IntoDeserializer, Deserialize};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+use serde::{
+    de::value::{
+        BorrowedStrDeserializer, StringDeserializer, U32Deserializer, U64Deserializer,
+        U8Deserializer, UnitDeserializer,
+    },
+    de::DeserializeOwned,
+    Deserialize,
+};
 
-use crate::{data_ids, ByteOrder, Compression, Error, FileHeader};
+use crate::{
+    data_ids,
+    encoding::{Encoding, Endianness},
+    read::{IoRead, Read, Reference, SliceRead},
+    Compression, Error, FileHeader, FormatVersion, Header, Limit, SbifOptions,
+};
 
-/// Deserializes a value from a byte slice.
-pub fn from_slice<'a, T: Deserialize<'a>>(bytes: &[u8]) -> Result<T, Error> {
-    let mut cursor = Cursor::new(bytes);
-    let mut deserializer = Deserializer::new(&mut cursor)?;
-    T::deserialize(&mut deserializer)
+/// Deserializes a value from a byte slice. Strings and bytes in uncompressed data can be borrowed from the slice.
+/// The slice must not contain any data after the value. The body may be in either byte order.
+pub fn from_slice<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> Result<T, Error> {
+    deserialize_slice(bytes, DeserializerOptions::default())
 }
 
-/// Deserializes a value from a reader.
-pub fn from_reader<'a, R: Read, T: Deserialize<'a>>(reader: R) -> Result<T, Error> {
-    let mut deserializer = Deserializer::new(reader)?;
-    T::deserialize(&mut deserializer)
+/// Deserializes a value from a byte slice using the deserializer settings of the given options. The body may
+/// be in either byte order, regardless of the byte order of the options.
+pub fn from_slice_with<'a, T: Deserialize<'a>>(
+    bytes: &'a [u8],
+    options: &SbifOptions<impl ByteOrder>,
+) -> Result<T, Error> {
+    deserialize_slice(bytes, options.deserializer_options().clone())
 }
 
-enum Reader<R: Read> {
-    None(R),
-    Deflate(DeflateDecoder<R>),
-    GZip(GzDecoder<R>),
-    ZLib(ZlibDecoder<R>),
+/// Deserializes a value from a reader. The reader must not contain any data after the value. The body may
+/// be in either byte order.
+pub fn from_reader<'a, R: io::Read, T: Deserialize<'a>>(mut reader: R) -> Result<T, Error> {
+    let options = DeserializerOptions::default();
+    let header = read_header_with(&mut reader, &options.accepted_magics)?;
+    let reader =
+        IoRead::new(reader, header.compression(), header.checksum()).map_err(Error::IoError)?;
+    deserialize_body(reader, header, options)
 }
 
-impl<R: Read> Read for Reader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        match self {
-            Self::None(r) => r.read(buf),
-            Self::Deflate(r) => r.read(buf),
-            Self::GZip(r) => r.read(buf),
-            Self::ZLib(r) => r.read(buf),
+fn deserialize_slice<'a, T: Deserialize<'a>>(
+    mut bytes: &'a [u8],
+    options: DeserializerOptions,
+) -> Result<T, Error> {
+    let header = read_header_with(&mut bytes, &options.accepted_magics)?;
+    let reader =
+        SliceRead::new(bytes, header.compression(), header.checksum()).map_err(Error::IoError)?;
+    deserialize_body(reader, header, options)
+}
+
+/// Deserializes the body with a deserializer for the byte order recorded in the header.
+fn deserialize_body<'de, R: Read<'de>, T: Deserialize<'de>>(
+    reader: R,
+    header: Header,
+    options: DeserializerOptions,
+) -> Result<T, Error> {
+    match header.endianness() {
+        Endianness::Big => deserialize_root(&mut Deserializer::<R, BigEndian>::from_read(
+            reader, header, options,
+        )),
+        Endianness::Little => deserialize_root(&mut Deserializer::<R, LittleEndian>::from_read(
+            reader, header, options,
+        )),
+    }
+}
+
+fn deserialize_root<'de, R: Read<'de>, B: ByteOrder, T: Deserialize<'de>>(
+    deserializer: &mut Deserializer<R, B>,
+) -> Result<T, Error> {
+    T::deserialize(&mut *deserializer)
+        .and_then(|value| deserializer.end().map(|()| value))
+        .map_err(|e| deserializer.attach_position(e))
+}
+
+/// Limits applied by a [`Deserializer`] to guard against malicious or corrupt input, and how the input is
+/// interpreted. Exceeding a limit returns [`Error::LimitExceeded`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeserializerOptions {
+    /// The maximum length of a single string or byte array.
+    pub max_allocation: usize,
+    /// The maximum number of bytes decoded from the input, after decompression.
+    pub max_total_bytes: usize,
+    /// The maximum number of elements in a sequence, tuple or map.
+    pub max_collection_length: usize,
+    /// The maximum depth of nested values.
+    pub max_depth: usize,
+    /// The value returned by `is_human_readable`, which must match the value used when serializing.
+    pub human_readable: bool,
+    /// The magic names at the start of the header that are accepted, see [`crate::SerializerOptions::magic`].
+    /// Other names return [`Error::InvalidHeader`].
+    pub accepted_magics: Vec<Cow<'static, str>>,
+}
+
+impl Default for DeserializerOptions {
+    fn default() -> Self {
+        Self {
+            max_allocation: usize::MAX,
+            max_total_bytes: usize::MAX,
+            max_collection_length: usize::MAX,
+            max_depth: 128,
+            human_readable: true,
+            accepted_magics: vec![Cow::Borrowed("SBIF")],
         }
     }
 }
 
-/// A deserializer for the SBIF format.
-pub struct Deserializer<R: Read>(BufPeekReader<Reader<R>>);
+/// A deserializer for the SBIF format, which reads bodies whose lengths and numbers are in the byte order `B`.
+/// [`crate::from_slice`] and [`crate::from_reader`] choose the byte order from the header.
+pub struct Deserializer<R, B = BigEndian> {
+    reader: R,
+    scratch: Vec<u8>,
+    options: DeserializerOptions,
+    depth: usize,
+    key: KeyCapture,
+    header: Header,
+    /// Whether the body has a checksum that has not been read yet.
+    checksum_pending: bool,
+    /// Copied from the header, as it is needed for every length and number.
+    encoding: Encoding<B>,
+    /// The interned strings read so far, in the order they were written.
+    strings: Vec<String>,
+}
+
+/// A step in the path to a value that failed to deserialize, which is added to the error as it is returned.
+enum PathSegment {
+    Index(usize),
+    Field(&'static str),
+    Variant(u32, Option<&'static str>),
+}
+
+impl fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "[{}]", index),
+            Self::Field(name) => write!(f, ".{}", name),
+            Self::Variant(_, Some(name)) => write!(f, ".{}", name),
+            Self::Variant(index, None) => write!(f, ".{}", index),
+        }
+    }
+}
 
-impl<R: Read> Deserializer<R> {
+impl<R: io::Read> Deserializer<IoRead<R>> {
     /// Creates a new deserializer from a reader, the reader must be at the start of the SBIF file and the method will return an error if the header is invalid.
     /// The compression type will be obtained from the header.
-    /// 
+    ///
+    /// Only big endian files can be read: this returns [`Error::ByteOrderMismatch`] for a file written in little
+    /// endian byte order. Use [`Deserializer::with_byte_order`] for such files, after checking the byte order with
+    /// [`read_header`] if it is not known, or [`from_reader`], which reads either byte order.
+    ///
     /// Example
     /// ```
     /// use serde_sbif::Deserializer;
@@ -57,39 +165,506 @@ impl<R: Read> Deserializer<R> {
     ///     T::deserialize(&mut deserializer).unwrap()
     /// }
     /// ```
-    pub fn new(mut reader: R) -> Result<Self, Error> {
-        let header = FileHeader::from_reader(&mut reader)?;
-
-        if header.header_name != "SBIF" {
-            return Err(Error::InvalidHeader(header.header_name));
-        } else if header.version != 1 {
-            return Err(Error::InvalidVersion {
-                expected: 1,
-                found: header.version,
-            });
+    pub fn new(reader: R) -> Result<Self, Error> {
+        Self::with_options(reader, DeserializerOptions::default())
+    }
+
+    /// Creates a new deserializer from a reader that enforces the given limits. The body must be big endian.
+    pub fn with_options(reader: R, options: DeserializerOptions) -> Result<Self, Error> {
+        Self::with_byte_order(reader, options)
+    }
+}
+
+impl<R: io::Read, B: ByteOrder> Deserializer<IoRead<R>, B> {
+    /// Creates a new deserializer from a reader that enforces the given limits, failing with
+    /// [`Error::ByteOrderMismatch`] if the header records a byte order other than `B`.
+    ///
+    /// Example
+    /// ```
+    /// use serde::Deserialize;
+    /// use serde_sbif::{Deserializer, DeserializerOptions, Endianness, LittleEndian, SbifOptions};
+    /// let options = SbifOptions::new().byte_order::<LittleEndian>();
+    /// let bytes = serde_sbif::to_bytes_with(&1.5_f64, &options).unwrap();
+    ///
+    /// let header = serde_sbif::read_header(bytes.as_slice()).unwrap();
+    /// assert_eq!(header.endianness(), Endianness::Little);
+    /// let mut deserializer =
+    ///     Deserializer::<_, LittleEndian>::with_byte_order(bytes.as_slice(), DeserializerOptions::default())
+    ///         .unwrap();
+    /// assert_eq!(f64::deserialize(&mut deserializer).unwrap(), 1.5);
+    /// assert!(Deserializer::new(bytes.as_slice()).is_err());
+    /// ```
+    pub fn with_byte_order(mut reader: R, options: DeserializerOptions) -> Result<Self, Error> {
+        let header = read_header_with(&mut reader, &options.accepted_magics)?;
+        check_byte_order::<B>(&header)?;
+        let reader =
+            IoRead::new(reader, header.compression(), header.checksum()).map_err(Error::IoError)?;
+        Ok(Self::from_read(reader, header, options))
+    }
+
+    /// Turns the deserializer into an iterator over the values that follow the header, as written by
+    /// serializing several values with the same [`crate::Serializer`].
+    ///
+    /// The values are read in the byte order `B` of the deserializer, so a stream of little endian values needs a
+    /// deserializer created with [`Deserializer::with_byte_order`]: [`Deserializer::new`] fails on such files
+    /// before a stream can be created.
+    ///
+    /// Example
+    /// ```
+    /// use serde::Serialize;
+    /// use serde_sbif::{Compression, Deserializer, Serializer};
+    /// let mut serializer = Serializer::new(Vec::new(), Compression::default()).unwrap();
+    /// for value in 0..3_u8 {
+    ///     value.serialize(&mut serializer).unwrap();
+    /// }
+    /// let bytes = serializer.finish().unwrap();
+    ///
+    /// let deserializer = Deserializer::new(bytes.as_slice()).unwrap();
+    /// let values = deserializer.into_stream::<u8>().collect::<Result<Vec<_>, _>>().unwrap();
+    /// assert_eq!(values, [0, 1, 2]);
+    /// ```
+    pub fn into_stream<T: DeserializeOwned>(self) -> StreamDeserializer<R, T, B> {
+        StreamDeserializer {
+            source: StreamSource::Bodies(Box::new(self)),
+            failed: false,
+            output: PhantomData,
+        }
+    }
+}
+
+impl<'a> Deserializer<SliceRead<'a>> {
+    /// Creates a new deserializer from a byte slice containing an SBIF file. If the data is uncompressed, strings and bytes
+    /// are borrowed from the slice rather than copied.
+    ///
+    /// Only big endian files can be read: this returns [`Error::ByteOrderMismatch`] for a file written in little
+    /// endian byte order. Use [`Deserializer::from_slice_with_byte_order`] for such files, or [`from_slice`], which
+    /// reads either byte order.
+    ///
+    /// Example
+    /// ```
+    /// use serde_sbif::{Compression, Deserializer};
+    /// let bytes = serde_sbif::to_bytes(&"Hello World!", Compression::None).unwrap();
+    /// let mut deserializer = Deserializer::from_slice(&bytes).unwrap();
+    /// let borrowed: &str = serde::Deserialize::deserialize(&mut deserializer).unwrap();
+    /// assert_eq!(borrowed, "Hello World!");
+    /// ```
+    pub fn from_slice(bytes: &'a [u8]) -> Result<Self, Error> {
+        Self::from_slice_with_options(bytes, DeserializerOptions::default())
+    }
+
+    /// Creates a new deserializer from a byte slice that enforces the given limits. The body must be big endian.
+    pub fn from_slice_with_options(
+        bytes: &'a [u8],
+        options: DeserializerOptions,
+    ) -> Result<Self, Error> {
+        Self::from_slice_with_byte_order(bytes, options)
+    }
+}
+
+impl<'a, B: ByteOrder> Deserializer<SliceRead<'a>, B> {
+    /// Creates a new deserializer from a byte slice that enforces the given limits, failing with
+    /// [`Error::ByteOrderMismatch`] if the header records a byte order other than `B`.
+    pub fn from_slice_with_byte_order(
+        mut bytes: &'a [u8],
+        options: DeserializerOptions,
+    ) -> Result<Self, Error> {
+        let header = read_header_with(&mut bytes, &options.accepted_magics)?;
+        check_byte_order::<B>(&header)?;
+        let reader = SliceRead::new(bytes, header.compression(), header.checksum())
+            .map_err(Error::IoError)?;
+        Ok(Self::from_read(reader, header, options))
+    }
+}
+
+impl<R, B: ByteOrder> Deserializer<R, B> {
+    /// Returns the underlying reader, which continues from the end of the last deserialized value. Data
+    /// stored after the document can be read with [`IoRead::into_inner`] or [`SliceRead::remaining`].
+    ///
+    /// Example
+    /// ```
+    /// use serde::Deserialize;
+    /// use serde_sbif::{Compression, Deserializer};
+    /// let mut bytes = serde_sbif::to_bytes(&"document", Compression::None).unwrap();
+    /// bytes.extend(b"trailer");
+    ///
+    /// let mut deserializer = Deserializer::from_slice(&bytes).unwrap();
+    /// let document = String::deserialize(&mut deserializer).unwrap();
+    /// assert_eq!(document, "document");
+    /// assert_eq!(deserializer.into_inner().remaining(), b"trailer");
+    /// ```
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Returns the header of the file being deserialized.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    fn from_read(reader: R, header: Header, options: DeserializerOptions) -> Self {
+        Self {
+            reader,
+            scratch: Vec::new(),
+            options,
+            depth: 0,
+            key: KeyCapture::default(),
+            encoding: Encoding::new(header.varint_encoding()),
+            checksum_pending: header.checksum().is_some(),
+            header,
+            strings: Vec::new(),
+        }
+    }
+}
+
+impl<'de, R: Read<'de>, B: ByteOrder> Deserializer<R, B> {
+    /// Checks that all of the input has been consumed, returning [`Error::TrailingData`] if any remains,
+    /// including data after the end of a compressed body. If the body has a checksum it is verified first,
+    /// returning [`Error::ChecksumMismatch`] if it differs. This should be called once the root value has been
+    /// deserialized.
+    pub fn end(&mut self) -> Result<(), Error> {
+        match self.at_end()? && self.reader.input_at_end().map_err(Error::IoError)? {
+            true => Ok(()),
+            false => Err(Error::TrailingData),
         }
+    }
 
-        let reader = match header.compression {
-            Compression::None => BufPeekReader::new(Reader::None(reader)),
-            Compression::Deflate(_) => {
-                BufPeekReader::new(Reader::Deflate(DeflateDecoder::new(reader)))
+    fn at_end(&mut self) -> Result<bool, Error> {
+        if self.checksum_pending {
+            // The values in a body with a checksum end at the checksum rather than at the end of the input.
+            match self.reader.peek_u8().map_err(Error::IoError)? {
+                data_ids::CHECKSUM_ID => self.read_checksum()?,
+                _ => return Ok(false),
             }
-            Compression::GZip(_) => BufPeekReader::new(Reader::GZip(GzDecoder::new(reader))),
-            Compression::ZLib(_) => BufPeekReader::new(Reader::ZLib(ZlibDecoder::new(reader))),
+        }
+
+        match self.reader.peek_u8() {
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(true),
+            Err(e) => Err(Error::IoError(e)),
+        }
+    }
+
+    /// Reads the checksum at the end of the body, if it has one, and compares it with the data read.
+    fn read_checksum(&mut self) -> Result<(), Error> {
+        let (true, Some(checksum)) = (self.checksum_pending, self.header.checksum()) else {
+            return Ok(());
         };
 
-        Ok(Self(reader))
+        let actual = self.reader.checksum().unwrap_or_default();
+        match self.reader.read_u8().map_err(Error::IoError)? {
+            data_ids::CHECKSUM_ID => {}
+            v => {
+                return Err(Error::InvalidDataId {
+                    expected: String::from("a checksum"),
+                    found: v,
+                })
+            }
+        }
+
+        let expected = checksum
+            .read_value(&mut self.reader)
+            .map_err(Error::IoError)?;
+        self.checksum_pending = false;
+        match expected == actual {
+            true => Ok(()),
+            false => Err(Error::ChecksumMismatch { expected, actual }),
+        }
+    }
+
+    /// Fails if the payload of a non-unit variant cannot be read without knowing the type of the variant. In
+    /// version 1 files the payload of a newtype variant starts with a data id, while the fields of tuple and
+    /// struct variants start with their number, whose first byte is zero like the id of unit and `None`.
+    fn check_untyped_variant(&mut self, variant: u32) -> Result<(), Error> {
+        if self.header.version() == FormatVersion::V1
+            && self.reader.peek_u8().map_err(Error::IoError)? == data_ids::NULL_ID
+        {
+            return Err(Error::UntypedVariant(variant));
+        }
+
+        Ok(())
+    }
+
+    /// Reads the length of a string or byte array and checks it against the allocation limits.
+    fn read_data_length(&mut self) -> Result<usize, Error> {
+        let length = self
+            .encoding
+            .read_length(&mut self.reader)
+            .map_err(Error::IoError)?;
+        if length > self.options.max_allocation {
+            return Err(Error::LimitExceeded {
+                limit: Limit::Allocation,
+                maximum: self.options.max_allocation,
+            });
+        }
+
+        self.check_total_bytes(length)?;
+        Ok(length)
+    }
+
+    /// Reads the length of a sequence, tuple or map and checks it against the collection length limit.
+    fn read_collection_length(&mut self) -> Result<usize, Error> {
+        let length = self
+            .encoding
+            .read_length(&mut self.reader)
+            .map_err(Error::IoError)?;
+        if length > self.options.max_collection_length {
+            return Err(Error::LimitExceeded {
+                limit: Limit::CollectionLength,
+                maximum: self.options.max_collection_length,
+            });
+        }
+
+        Ok(length)
+    }
+
+    fn check_total_bytes(&self, additional: usize) -> Result<(), Error> {
+        if self.reader.byte_offset().saturating_add(additional) > self.options.max_total_bytes {
+            Err(Error::LimitExceeded {
+                limit: Limit::TotalBytes,
+                maximum: self.options.max_total_bytes,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Runs `f` one level deeper, failing if that exceeds the depth limit.
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        if self.depth >= self.options.max_depth {
+            return Err(Error::LimitExceeded {
+                limit: Limit::Depth,
+                maximum: self.options.max_depth,
+            });
+        }
+
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<Vec<u8>, Error> {
+        match self
+            .reader
+            .read_slice(length, &mut self.scratch)
+            .map_err(Error::IoError)?
+        {
+            Reference::Borrowed(bytes) => Ok(bytes.to_vec()),
+            Reference::Copied(bytes) => Ok(bytes.to_vec()),
+        }
+    }
+
+    /// Wraps an error with the current byte offset and the path of the root value, unless it already has them.
+    fn attach_position(&self, error: Error) -> Error {
+        match error {
+            Error::Positioned { .. } => error,
+            error => Error::Positioned {
+                offset: self.reader.byte_offset(),
+                path: String::from("."),
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// Runs `f` to deserialize the value at `segment`, adding the segment to the start of the path of any
+    /// error, so that the path is only built once deserialization fails.
+    fn with_segment<T>(
+        &mut self,
+        segment: PathSegment,
+        f: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        f(self).map_err(|e| match self.attach_position(e) {
+            Error::Positioned {
+                offset,
+                path,
+                error,
+            } => Error::Positioned {
+                offset,
+                // The path of the value itself replaces the path of the root value.
+                path: match path.as_str() {
+                    "." => segment.to_string(),
+                    _ => format!("{}{}", segment, path),
+                },
+                error,
+            },
+            error => error,
+        })
+    }
+
+    fn read_str<V: serde::de::Visitor<'de>>(
+        &mut self,
+        length: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match self
+            .reader
+            .read_slice(length, &mut self.scratch)
+            .map_err(Error::IoError)?
+        {
+            Reference::Borrowed(bytes) => {
+                let string = std::str::from_utf8(bytes).map_err(Error::Utf8Error)?;
+                self.key.record(string);
+                visitor.visit_borrowed_str(string)
+            }
+            Reference::Copied(bytes) => {
+                let string = std::str::from_utf8(bytes).map_err(Error::Utf8Error)?;
+                self.key.record(string);
+                visitor.visit_str(string)
+            }
+        }
+    }
+
+    /// Reads the id of a struct, returning whether its fields are positional.
+    fn read_struct_id(&mut self) -> Result<bool, Error> {
+        match self.reader.read_u8().map_err(Error::IoError)? {
+            data_ids::MAP_ID => Ok(false),
+            data_ids::TUPLE_ID => Ok(true),
+            found => Err(Error::InvalidDataId {
+                expected: format!("{} or {}", data_ids::MAP_ID, data_ids::TUPLE_ID),
+                found,
+            }),
+        }
+    }
+
+    /// Reads the fields of a struct whose id has already been read. Positional structs are presented
+    /// to the visitor as a map from the names in `fields` to the values in the same position.
+    fn read_struct<V: serde::de::Visitor<'de>>(
+        &mut self,
+        positional: bool,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let length = self.read_collection_length()?;
+        if !positional {
+            return visitor.visit_map(MapAccess {
+                fields,
+                ..MapAccess::new(self, length)
+            });
+        }
+
+        if length != fields.len() {
+            return Err(Error::InvalidLength {
+                expected: fields.len(),
+                actual: length,
+                message: String::from("Invalid struct field count"),
+            });
+        }
+
+        visitor.visit_map(FieldAccess {
+            de: self,
+            fields,
+            current: 0,
+        })
+    }
+
+    /// Reads a string whose id has already been read, resolving references to interned strings.
+    fn read_string<V: serde::de::Visitor<'de>>(
+        &mut self,
+        id: u8,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        match id {
+            data_ids::STR_ID => {
+                let length = self.read_data_length()?;
+                self.read_str(length, visitor)
+            }
+            data_ids::INTERNED_STR_ID => {
+                let length = self.read_data_length()?;
+                let buffer = self.read_bytes(length)?;
+                let string = String::from_utf8(buffer).map_err(Error::FromUtf8Error)?;
+                self.key.record(&string);
+                self.strings.push(string.clone());
+                visitor.visit_string(string)
+            }
+            data_ids::STR_REF_ID => {
+                let index = self
+                    .encoding
+                    .read_u32(&mut self.reader)
+                    .map_err(Error::IoError)?;
+                let string =
+                    self.strings
+                        .get(index as usize)
+                        .ok_or(Error::InvalidStringReference {
+                            index,
+                            interned: self.strings.len(),
+                        })?;
+                self.key.record(string);
+                visitor.visit_str(string)
+            }
+            found => Err(Error::InvalidDataId {
+                expected: String::from("a string"),
+                found,
+            }),
+        }
+    }
+}
+
+/// Reads the header at the start of an SBIF file, leaving the reader at the start of the body. Only the "SBIF"
+/// magic name is accepted, use [`read_header_with`] or [`SbifOptions::read_header`] for branded files.
+///
+/// Example
+/// ```
+/// use serde_sbif::Compression;
+/// let bytes = serde_sbif::to_bytes(&"Hello World!", Compression::Deflate(6)).unwrap();
+/// let header = serde_sbif::read_header(bytes.as_slice()).unwrap();
+/// assert_eq!(header.name(), "SBIF");
+/// assert_eq!(header.compression(), Compression::Deflate(6));
+/// ```
+pub fn read_header<R: io::Read>(reader: R) -> Result<Header, Error> {
+    read_header_with(reader, &["SBIF"])
+}
+
+/// Reads the header at the start of a file whose magic name is one of `accepted_magics`, leaving the reader at the
+/// start of the body. Other names return [`Error::InvalidHeader`] before the rest of the header is read.
+///
+/// Example
+/// ```
+/// use serde_sbif::SbifOptions;
+/// let options = SbifOptions::new().magic("MYGAME-SAVE");
+/// let bytes = serde_sbif::to_bytes_with(&"save data", &options).unwrap();
+/// let header = serde_sbif::read_header_with(bytes.as_slice(), &["SBIF", "MYGAME-SAVE"]).unwrap();
+/// assert_eq!(header.name(), "MYGAME-SAVE");
+/// assert!(serde_sbif::read_header_with(bytes.as_slice(), &["SBIF"]).is_err());
+/// ```
+pub fn read_header_with<R: io::Read, M: AsRef<str>>(
+    mut reader: R,
+    accepted_magics: &[M],
+) -> Result<Header, Error> {
+    let header_name = FileHeader::read_name(&mut reader)?;
+
+    if !accepted_magics
+        .iter()
+        .any(|magic| magic.as_ref() == header_name)
+    {
+        let expected = accepted_magics
+            .iter()
+            .map(|magic| format!("'{}'", magic.as_ref()))
+            .collect::<Vec<_>>();
+        return Err(Error::InvalidHeader {
+            expected: expected.join(" or "),
+            found: header_name,
+        });
+    }
+
+    FileHeader::from_reader(&mut reader, header_name).map(Header)
+}
+
+/// Fails if the body described by the header is not in the byte order `B`.
+fn check_byte_order<B: ByteOrder>(header: &Header) -> Result<(), Error> {
+    match (Endianness::of::<B>(), header.endianness()) {
+        (expected, found) if expected == found => Ok(()),
+        (expected, found) => Err(Error::ByteOrderMismatch { expected, found }),
     }
 }
 
-impl<'de, 'a, R: Read> serde::de::Deserializer<'de> for &'a mut Deserializer<R> {
+impl<'de, R: Read<'de>, B: ByteOrder> serde::de::Deserializer<'de> for &mut Deserializer<R, B> {
     type Error = Error;
 
     fn deserialize_any<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        let id = self.0.peek().read_u8().map_err(Error::IoError)?;
+        let id = self.reader.peek_u8().map_err(Error::IoError)?;
         match id {
             data_ids::NULL_ID => self.deserialize_option(visitor),
             data_ids::BOOL_ID => self.deserialize_bool(visitor),
@@ -97,35 +672,57 @@ impl<'de, 'a, R: Read> serde::de::Deserializer<'de> for &'a mut Deserializer<R>
             data_ids::I16_ID => self.deserialize_i16(visitor),
             data_ids::I32_ID => self.deserialize_i32(visitor),
             data_ids::I64_ID => self.deserialize_i64(visitor),
+            data_ids::I128_ID => self.deserialize_i128(visitor),
             data_ids::U8_ID => self.deserialize_u8(visitor),
             data_ids::U16_ID => self.deserialize_u16(visitor),
             data_ids::U32_ID => self.deserialize_u32(visitor),
             data_ids::U64_ID => self.deserialize_u64(visitor),
+            data_ids::U128_ID => self.deserialize_u128(visitor),
             data_ids::F32_ID => self.deserialize_f32(visitor),
             data_ids::F64_ID => self.deserialize_f64(visitor),
             data_ids::CHAR_ID => self.deserialize_char(visitor),
-            data_ids::STR_ID => self.deserialize_str(visitor),
+            data_ids::STR_ID | data_ids::INTERNED_STR_ID | data_ids::STR_REF_ID => {
+                self.deserialize_str(visitor)
+            }
             data_ids::BYTES_ID => self.deserialize_bytes(visitor),
-            data_ids::SEQ_ID => self.deserialize_seq(visitor),
+            data_ids::SEQ_ID | data_ids::PACKED_SEQ_ID => self.deserialize_seq(visitor),
             data_ids::MAP_ID => self.deserialize_map(visitor),
-            data_ids::TUPLE_ID => {
-                self.0.read_u8().map_err(Error::IoError)?;
-                let length = self.0.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
-                visitor.visit_seq(SeqAccess::new(self, length))
+            data_ids::TUPLE_ID | data_ids::TUPLE_STRUCT_ID => {
+                self.reader.read_u8().map_err(Error::IoError)?;
+                let length = self.read_collection_length()?;
+                self.nested(|de| visitor.visit_seq(SeqAccess::new(de, length)))
             }
-            data_ids::UNIT_VARIANT_ID => {
-                self.0.read_u8().map_err(Error::IoError)?;
-                let variant = self.0.read_u32::<ByteOrder>().map_err(Error::IoError)?;
-                visitor.visit_enum(variant.into_deserializer())
+            data_ids::UNIT_VARIANT_ID | data_ids::ENUM_VARIANT_ID => {
+                self.reader.read_u8().map_err(Error::IoError)?;
+                let variant = self
+                    .encoding
+                    .read_u32(&mut self.reader)
+                    .map_err(Error::IoError)?;
+                if id == data_ids::ENUM_VARIANT_ID {
+                    self.check_untyped_variant(variant)?;
+                }
+                self.nested(|de| {
+                    visitor.visit_map(VariantMapAccess {
+                        de,
+                        variant: Some(variant),
+                        key_read: false,
+                        is_unit: id == data_ids::UNIT_VARIANT_ID,
+                    })
+                })
             }
-            data_ids::ENUM_VARIANT_ID => visitor.visit_enum(EnumAccess { de: self }),
-            data_ids::TUPLE_STRUCT_ID => {
-                self.0.read_u8().map_err(Error::IoError)?;
-                let length = self.0.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
-                visitor.visit_seq(SeqAccess::new(self, length))
+            data_ids::NAMED_UNIT_VARIANT_ID | data_ids::NAMED_ENUM_VARIANT_ID => {
+                self.reader.read_u8().map_err(Error::IoError)?;
+                self.nested(|de| {
+                    visitor.visit_map(VariantMapAccess {
+                        de,
+                        variant: None,
+                        key_read: false,
+                        is_unit: id == data_ids::NAMED_UNIT_VARIANT_ID,
+                    })
+                })
             }
             found => Err(Error::InvalidDataId {
-                expected: format!("from {} to {}", data_ids::NULL_ID, data_ids::MAP_ID),
+                expected: format!("from {} to {}", data_ids::NULL_ID, data_ids::LAST_ID),
                 found,
             }),
         }
@@ -135,106 +732,162 @@ impl<'de, 'a, R: Read> serde::de::Deserializer<'de> for &'a mut Deserializer<R>
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::BOOL_ID)?;
-        visitor.visit_bool(self.0.read_u8().map_err(Error::IoError)? != 0)
+        read_id(&mut self.reader, data_ids::BOOL_ID)?;
+        visitor.visit_bool(self.reader.read_u8().map_err(Error::IoError)? != 0)
     }
 
     fn deserialize_i8<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::I8_ID)?;
-        visitor.visit_i8(self.0.read_i8().map_err(Error::IoError)?)
+        read_id(&mut self.reader, data_ids::I8_ID)?;
+        visitor.visit_i8(self.reader.read_i8().map_err(Error::IoError)?)
     }
 
     fn deserialize_i16<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::I16_ID)?;
-        visitor.visit_i16(self.0.read_i16::<ByteOrder>().map_err(Error::IoError)?)
+        read_id(&mut self.reader, data_ids::I16_ID)?;
+        visitor.visit_i16(
+            self.encoding
+                .read_i16(&mut self.reader)
+                .map_err(Error::IoError)?,
+        )
     }
 
     fn deserialize_i32<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::I32_ID)?;
-        visitor.visit_i32(self.0.read_i32::<ByteOrder>().map_err(Error::IoError)?)
+        read_id(&mut self.reader, data_ids::I32_ID)?;
+        visitor.visit_i32(
+            self.encoding
+                .read_i32(&mut self.reader)
+                .map_err(Error::IoError)?,
+        )
     }
 
     fn deserialize_i64<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::I64_ID)?;
-        visitor.visit_i64(self.0.read_i64::<ByteOrder>().map_err(Error::IoError)?)
+        read_id(&mut self.reader, data_ids::I64_ID)?;
+        visitor.visit_i64(
+            self.encoding
+                .read_i64(&mut self.reader)
+                .map_err(Error::IoError)?,
+        )
+    }
+
+    fn deserialize_i128<V: serde::de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        read_id(&mut self.reader, data_ids::I128_ID)?;
+        visitor.visit_i128(
+            self.encoding
+                .read_i128(&mut self.reader)
+                .map_err(Error::IoError)?,
+        )
     }
 
     fn deserialize_u8<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::U8_ID)?;
-        visitor.visit_u8(self.0.read_u8().map_err(Error::IoError)?)
+        read_id(&mut self.reader, data_ids::U8_ID)?;
+        visitor.visit_u8(self.reader.read_u8().map_err(Error::IoError)?)
     }
 
     fn deserialize_u16<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::U16_ID)?;
-        visitor.visit_u16(self.0.read_u16::<ByteOrder>().map_err(Error::IoError)?)
+        read_id(&mut self.reader, data_ids::U16_ID)?;
+        visitor.visit_u16(
+            self.encoding
+                .read_u16(&mut self.reader)
+                .map_err(Error::IoError)?,
+        )
     }
 
     fn deserialize_u32<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::U32_ID)?;
-        visitor.visit_u32(self.0.read_u32::<ByteOrder>().map_err(Error::IoError)?)
+        read_id(&mut self.reader, data_ids::U32_ID)?;
+        visitor.visit_u32(
+            self.encoding
+                .read_u32(&mut self.reader)
+                .map_err(Error::IoError)?,
+        )
     }
 
     fn deserialize_u64<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::U64_ID)?;
-        visitor.visit_u64(self.0.read_u64::<ByteOrder>().map_err(Error::IoError)?)
+        read_id(&mut self.reader, data_ids::U64_ID)?;
+        visitor.visit_u64(
+            self.encoding
+                .read_u64(&mut self.reader)
+                .map_err(Error::IoError)?,
+        )
+    }
+
+    fn deserialize_u128<V: serde::de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        read_id(&mut self.reader, data_ids::U128_ID)?;
+        visitor.visit_u128(
+            self.encoding
+                .read_u128(&mut self.reader)
+                .map_err(Error::IoError)?,
+        )
     }
 
     fn deserialize_f32<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::F32_ID)?;
-        visitor.visit_f32(self.0.read_f32::<ByteOrder>().map_err(Error::IoError)?)
+        read_id(&mut self.reader, data_ids::F32_ID)?;
+        visitor.visit_f32(
+            self.encoding
+                .read_f32(&mut self.reader)
+                .map_err(Error::IoError)?,
+        )
     }
 
     fn deserialize_f64<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::F64_ID)?;
-        visitor.visit_f64(self.0.read_f64::<ByteOrder>().map_err(Error::IoError)?)
+        read_id(&mut self.reader, data_ids::F64_ID)?;
+        visitor.visit_f64(
+            self.encoding
+                .read_f64(&mut self.reader)
+                .map_err(Error::IoError)?,
+        )
     }
 
     fn deserialize_char<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::CHAR_ID)?;
-        let mut bytes = vec![self.0.read_u8().map_err(Error::IoError)?];
+        read_id(&mut self.reader, data_ids::CHAR_ID)?;
+        let mut bytes = vec![self.reader.read_u8().map_err(Error::IoError)?];
 
         if bytes[0] & 0b1110_0000 == 0b1100_0000 {
-            bytes.push(self.0.read_u8().map_err(Error::IoError)?);
+            bytes.push(self.reader.read_u8().map_err(Error::IoError)?);
         } else if bytes[0] & 0b1111_0000 == 0b1110_0000 {
-            bytes.push(self.0.read_u8().map_err(Error::IoError)?);
-            bytes.push(self.0.read_u8().map_err(Error::IoError)?);
+            bytes.push(self.reader.read_u8().map_err(Error::IoError)?);
+            bytes.push(self.reader.read_u8().map_err(Error::IoError)?);
         } else if bytes[0] & 0b1111_1000 == 0b1111_0000 {
-            bytes.push(self.0.read_u8().map_err(Error::IoError)?);
-            bytes.push(self.0.read_u8().map_err(Error::IoError)?);
-            bytes.push(self.0.read_u8().map_err(Error::IoError)?);
+            bytes.push(self.reader.read_u8().map_err(Error::IoError)?);
+            bytes.push(self.reader.read_u8().map_err(Error::IoError)?);
+            bytes.push(self.reader.read_u8().map_err(Error::IoError)?);
         }
 
         let string = String::from_utf8(bytes).map_err(Error::FromUtf8Error)?;
@@ -245,44 +898,49 @@ impl<'de, 'a, R: Read> serde::de::Deserializer<'de> for &'a mut Deserializer<R>
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::STR_ID)?;
-        let length = self.0.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
-        let mut buffer = vec![0_u8; length];
-        self.0.read_exact(&mut buffer).map_err(Error::IoError)?;
-        let string = String::from_utf8(buffer).map_err(Error::FromUtf8Error)?;
-        visitor.visit_str(&string)
+        let id = self.reader.read_u8().map_err(Error::IoError)?;
+        self.read_string(id, visitor)
     }
 
     fn deserialize_string<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::STR_ID)?;
-        let length = self.0.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
-        let mut buffer = vec![0_u8; length];
-        self.0.read_exact(&mut buffer).map_err(Error::IoError)?;
-        visitor.visit_string(String::from_utf8(buffer).map_err(Error::FromUtf8Error)?)
+        let id = self.reader.read_u8().map_err(Error::IoError)?;
+        if id != data_ids::STR_ID {
+            return self.read_string(id, visitor);
+        }
+
+        let length = self.read_data_length()?;
+        let buffer = self.read_bytes(length)?;
+        let string = String::from_utf8(buffer).map_err(Error::FromUtf8Error)?;
+        self.key.record(&string);
+        visitor.visit_string(string)
     }
 
     fn deserialize_bytes<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::BYTES_ID)?;
-        let length = self.0.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
-        let mut buffer = vec![0_u8; length];
-        self.0.read_exact(&mut buffer).map_err(Error::IoError)?;
-        visitor.visit_bytes(&buffer)
+        read_id(&mut self.reader, data_ids::BYTES_ID)?;
+        let length = self.read_data_length()?;
+        match self
+            .reader
+            .read_slice(length, &mut self.scratch)
+            .map_err(Error::IoError)?
+        {
+            Reference::Borrowed(bytes) => visitor.visit_borrowed_bytes(bytes),
+            Reference::Copied(bytes) => visitor.visit_bytes(bytes),
+        }
     }
 
     fn deserialize_byte_buf<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::BYTES_ID)?;
-        let length = self.0.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
-        let mut buffer = vec![0_u8; length];
-        self.0.read_exact(&mut buffer).map_err(Error::IoError)?;
+        read_id(&mut self.reader, data_ids::BYTES_ID)?;
+        let length = self.read_data_length()?;
+        let buffer = self.read_bytes(length)?;
         visitor.visit_byte_buf(buffer)
     }
 
@@ -290,13 +948,13 @@ impl<'de, 'a, R: Read> serde::de::Deserializer<'de> for &'a mut Deserializer<R>
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        let peek_id = self.0.peek().read_u8().map_err(Error::IoError)?;
+        let peek_id = self.reader.peek_u8().map_err(Error::IoError)?;
         match peek_id {
             data_ids::NULL_ID => {
-                self.0.read_u8().map_err(Error::IoError)?;
+                self.reader.read_u8().map_err(Error::IoError)?;
                 visitor.visit_none()
             }
-            _ => visitor.visit_some(self),
+            _ => self.nested(|de| visitor.visit_some(de)),
         }
     }
 
@@ -304,7 +962,7 @@ impl<'de, 'a, R: Read> serde::de::Deserializer<'de> for &'a mut Deserializer<R>
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::NULL_ID)?;
+        read_id(&mut self.reader, data_ids::NULL_ID)?;
         visitor.visit_unit()
     }
 
@@ -318,19 +976,58 @@ impl<'de, 'a, R: Read> serde::de::Deserializer<'de> for &'a mut Deserializer<R>
 
     fn deserialize_newtype_struct<V: serde::de::Visitor<'de>>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        visitor.visit_newtype_struct(self)
+        self.nested(|de| {
+            if name == crate::value::VALUE_TOKEN {
+                visitor.visit_enum(ValueAccess { de })
+            } else {
+                visitor.visit_newtype_struct(de)
+            }
+        })
     }
 
     fn deserialize_seq<V: serde::de::Visitor<'de>>(
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::SEQ_ID)?;
-        let length = self.0.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
-        visitor.visit_seq(SeqAccess::new(self, length))
+        match self.reader.read_u8().map_err(Error::IoError)? {
+            data_ids::SEQ_ID => {
+                let length = self.read_collection_length()?;
+                self.nested(|de| visitor.visit_seq(SeqAccess::new(de, length)))
+            }
+            data_ids::PACKED_SEQ_ID => {
+                let id = self.reader.read_u8().map_err(Error::IoError)?;
+                if data_ids::packed_width(id).is_none() {
+                    return Err(Error::InvalidDataId {
+                        expected: String::from("a numeric id"),
+                        found: id,
+                    });
+                }
+
+                let length = self.read_collection_length()?;
+                self.nested(|de| {
+                    visitor.visit_seq(SeqAccess {
+                        packed: Some(id),
+                        ..SeqAccess::new(de, length)
+                    })
+                })
+            }
+            data_ids::BYTES_ID => {
+                let length = self.read_data_length()?;
+                self.nested(|de| visitor.visit_seq(SeqAccess::bytes(de, length)))
+            }
+            found => Err(Error::InvalidDataId {
+                expected: format!(
+                    "{}, {} or {}",
+                    data_ids::SEQ_ID,
+                    data_ids::PACKED_SEQ_ID,
+                    data_ids::BYTES_ID
+                ),
+                found,
+            }),
+        }
     }
 
     fn deserialize_tuple<V: serde::de::Visitor<'de>>(
@@ -338,16 +1035,30 @@ impl<'de, 'a, R: Read> serde::de::Deserializer<'de> for &'a mut Deserializer<R>
         len: usize,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::TUPLE_ID)?;
-        let length = self.0.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
+        let (length, packed) = match self.reader.read_u8().map_err(Error::IoError)? {
+            data_ids::TUPLE_ID => (self.read_collection_length()?, None),
+            data_ids::BYTES_ID => (self.read_data_length()?, Some(data_ids::U8_ID)),
+            found => {
+                return Err(Error::InvalidDataId {
+                    expected: format!("{} or {}", data_ids::TUPLE_ID, data_ids::BYTES_ID),
+                    found,
+                })
+            }
+        };
+
         if length != len {
-            return Err(Error::InvalidLength {
+            Err(Error::InvalidLength {
                 expected: len,
                 actual: length,
                 message: String::from("Invalid tuple length"),
-            });
+            })
         } else {
-            visitor.visit_seq(SeqAccess::new(self, length))
+            self.nested(|de| {
+                visitor.visit_seq(SeqAccess {
+                    packed,
+                    ..SeqAccess::new(de, length)
+                })
+            })
         }
     }
 
@@ -357,16 +1068,16 @@ impl<'de, 'a, R: Read> serde::de::Deserializer<'de> for &'a mut Deserializer<R>
         len: usize,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::TUPLE_STRUCT_ID)?;
-        let length = self.0.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
+        read_id(&mut self.reader, data_ids::TUPLE_STRUCT_ID)?;
+        let length = self.read_collection_length()?;
         if length != len {
-            return Err(Error::InvalidLength {
+            Err(Error::InvalidLength {
                 expected: len,
                 actual: length,
                 message: String::from("Invalid tuple struct length"),
-            });
+            })
         } else {
-            visitor.visit_seq(SeqAccess::new(self, length))
+            self.nested(|de| visitor.visit_seq(SeqAccess::new(de, length)))
         }
     }
 
@@ -374,20 +1085,19 @@ impl<'de, 'a, R: Read> serde::de::Deserializer<'de> for &'a mut Deserializer<R>
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::MAP_ID)?;
-        let length = self.0.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
-        visitor.visit_map(MapAccess::new(self, length))
+        read_id(&mut self.reader, data_ids::MAP_ID)?;
+        let length = self.read_collection_length()?;
+        self.nested(|de| visitor.visit_map(MapAccess::new(de, length)))
     }
 
     fn deserialize_struct<V: serde::de::Visitor<'de>>(
         self,
         _name: &'static str,
-        _fields: &'static [&'static str],
+        fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        read_id(&mut self.0, data_ids::MAP_ID)?;
-        let length = self.0.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
-        visitor.visit_map(MapAccess::new(self, length))
+        let positional = self.read_struct_id()?;
+        self.nested(|de| de.read_struct(positional, fields, visitor))
     }
 
     fn deserialize_enum<V: serde::de::Visitor<'de>>(
@@ -396,23 +1106,36 @@ impl<'de, 'a, R: Read> serde::de::Deserializer<'de> for &'a mut Deserializer<R>
         variants: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        let mut peek = self.0.peek();
-        let data_id = peek.read_u8().map_err(Error::IoError)?;
-        let variant_index = peek.read_u32::<ByteOrder>().map_err(Error::IoError)?;
-        drop(peek);
+        let data_id = self.reader.peek_u8().map_err(Error::IoError)?;
 
         match data_id {
             data_ids::UNIT_VARIANT_ID => {
-                self.0.read_u8().map_err(Error::IoError)?;
-                self.0.read_u32::<ByteOrder>().map_err(Error::IoError)?;
-                visitor.visit_enum(variants[variant_index as usize].into_deserializer())
+                self.reader.read_u8().map_err(Error::IoError)?;
+                let variant_index = self
+                    .encoding
+                    .read_u32(&mut self.reader)
+                    .map_err(Error::IoError)?;
+                visitor.visit_enum(U32Deserializer::<Error>::new(variant_index))
+            }
+            data_ids::NAMED_UNIT_VARIANT_ID => {
+                self.reader.read_u8().map_err(Error::IoError)?;
+                let variant = String::deserialize(&mut *self)?;
+                visitor.visit_enum(StringDeserializer::<Error>::new(variant))
             }
-            data_ids::ENUM_VARIANT_ID => visitor.visit_enum(EnumAccess { de: self }),
+            data_ids::ENUM_VARIANT_ID | data_ids::NAMED_ENUM_VARIANT_ID => self.nested(|de| {
+                visitor.visit_enum(EnumAccess {
+                    de,
+                    variants,
+                    segment: PathSegment::Variant(0, None),
+                })
+            }),
             found => Err(Error::InvalidDataId {
                 expected: format!(
-                    "{} or {}",
+                    "{}, {}, {} or {}",
                     data_ids::UNIT_VARIANT_ID,
-                    data_ids::ENUM_VARIANT_ID
+                    data_ids::ENUM_VARIANT_ID,
+                    data_ids::NAMED_UNIT_VARIANT_ID,
+                    data_ids::NAMED_ENUM_VARIANT_ID
                 ),
                 found,
             }),
@@ -423,17 +1146,19 @@ impl<'de, 'a, R: Read> serde::de::Deserializer<'de> for &'a mut Deserializer<R>
         self,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        let data_id = self.0.read_u8().map_err(Error::IoError)?;
-        let argument = self.0.read_u32::<ByteOrder>().map_err(Error::IoError)?;
-
-        match data_id {
-            data_ids::STR_ID => {
-                let mut buffer = vec![0_u8; argument as usize];
-                self.0.read_exact(&mut buffer).map_err(Error::IoError)?;
-                let string = String::from_utf8(buffer).map_err(Error::FromUtf8Error)?;
-                visitor.visit_str(&string)
+        match self.reader.read_u8().map_err(Error::IoError)? {
+            id @ (data_ids::STR_ID | data_ids::INTERNED_STR_ID | data_ids::STR_REF_ID) => {
+                self.read_string(id, visitor)
             }
-            data_ids::UNIT_VARIANT_ID | data_ids::ENUM_VARIANT_ID => visitor.visit_u32(argument),
+            data_ids::NAMED_UNIT_VARIANT_ID | data_ids::NAMED_ENUM_VARIANT_ID => {
+                let id = self.reader.read_u8().map_err(Error::IoError)?;
+                self.read_string(id, visitor)
+            }
+            data_ids::UNIT_VARIANT_ID | data_ids::ENUM_VARIANT_ID => visitor.visit_u32(
+                self.encoding
+                    .read_u32(&mut self.reader)
+                    .map_err(Error::IoError)?,
+            ),
             v => Err(Error::InvalidDataId {
                 expected: String::from("an identifier"),
                 found: v,
@@ -447,25 +1172,179 @@ impl<'de, 'a, R: Read> serde::de::Deserializer<'de> for &'a mut Deserializer<R>
     ) -> Result<V::Value, Self::Error> {
         self.deserialize_any(visitor)
     }
+
+    fn is_human_readable(&self) -> bool {
+        self.options.human_readable
+    }
+}
+
+/// An iterator over consecutive values read from a stream, created with [`Deserializer::into_stream`] for
+/// values that share a header or [`StreamDeserializer::documents`] for complete documents written one
+/// after another. Iteration ends cleanly when the input ends between values, and stops after the
+/// first error.
+pub struct StreamDeserializer<R: io::Read, T, B: ByteOrder = BigEndian> {
+    source: StreamSource<R, B>,
+    failed: bool,
+    output: PhantomData<T>,
+}
+
+enum StreamSource<R: io::Read, B: ByteOrder> {
+    /// Values following a single header, in the byte order `B`.
+    Bodies(Box<Deserializer<IoRead<R>, B>>),
+    /// Documents that each have their own header and contain a single value.
+    Documents {
+        reader: Option<io::BufReader<R>>,
+        options: DeserializerOptions,
+    },
+}
+
+impl<R: io::Read, T: DeserializeOwned> StreamDeserializer<R, T> {
+    /// Creates an iterator over complete documents concatenated in a reader, such as those written by
+    /// repeatedly calling [`crate::to_writer`] on the same writer.
+    ///
+    /// Example
+    /// ```
+    /// use serde_sbif::{Compression, StreamDeserializer};
+    /// let mut bytes = Vec::new();
+    /// serde_sbif::to_writer(&mut bytes, &"first", Compression::default()).unwrap();
+    /// serde_sbif::to_writer(&mut bytes, &"second", Compression::None).unwrap();
+    ///
+    /// let values = StreamDeserializer::<_, String>::documents(bytes.as_slice())
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .unwrap();
+    /// assert_eq!(values, ["first", "second"]);
+    /// ```
+    pub fn documents(reader: R) -> Self {
+        Self::documents_with_options(reader, DeserializerOptions::default())
+    }
+
+    /// Creates an iterator over complete documents that enforces the given limits on each document. Each
+    /// document may be in either byte order.
+    pub fn documents_with_options(reader: R, options: DeserializerOptions) -> Self {
+        Self {
+            source: StreamSource::Documents {
+                reader: Some(io::BufReader::new(reader)),
+                options,
+            },
+            failed: false,
+            output: PhantomData,
+        }
+    }
+}
+
+impl<R: io::Read, T: DeserializeOwned, B: ByteOrder> StreamDeserializer<R, T, B> {
+    fn next_body(deserializer: &mut Deserializer<IoRead<R>, B>) -> Option<Result<T, Error>> {
+        match deserializer.at_end() {
+            Ok(true) => None,
+            Ok(false) => Some(
+                T::deserialize(&mut *deserializer).map_err(|e| deserializer.attach_position(e)),
+            ),
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    fn next_document(
+        slot: &mut Option<io::BufReader<R>>,
+        options: &DeserializerOptions,
+    ) -> Option<Result<T, Error>> {
+        let mut reader = slot.take()?;
+        match reader.fill_buf() {
+            Ok([]) => return None,
+            Ok(_) => {}
+            Err(e) => return Some(Err(Error::IoError(e))),
+        }
+
+        let header = match read_header_with(&mut reader, &options.accepted_magics) {
+            Ok(header) => header,
+            Err(e) => return Some(Err(e)),
+        };
+        let (compression, checksum) = (header.compression(), header.checksum());
+        let reader = match IoRead::from_buf_reader(reader, compression, checksum) {
+            Ok(reader) => reader,
+            Err(e) => return Some(Err(Error::IoError(e))),
+        };
+
+        let (value, reader) = match header.endianness() {
+            Endianness::Big => Self::read_document::<BigEndian>(reader, header, options.clone()),
+            Endianness::Little => {
+                Self::read_document::<LittleEndian>(reader, header, options.clone())
+            }
+        };
+
+        *slot = Some(reader.into_inner());
+        Some(value)
+    }
+
+    /// Reads the value of a document whose body is in the byte order `O`, returning the reader so that the
+    /// next document can be read from it.
+    fn read_document<O: ByteOrder>(
+        reader: IoRead<R>,
+        header: Header,
+        options: DeserializerOptions,
+    ) -> (Result<T, Error>, IoRead<R>) {
+        let mut deserializer = Deserializer::<_, O>::from_read(reader, header, options);
+        let value = T::deserialize(&mut deserializer)
+            .and_then(|value| match deserializer.header.compression() {
+                Compression::None => deserializer.read_checksum().map(|()| value),
+                // Reading to the end of compressed data also consumes the trailer of its format, leaving
+                // the next document in the input.
+                _ => match deserializer.at_end()? {
+                    true => Ok(value),
+                    false => Err(Error::TrailingData),
+                },
+            })
+            .map_err(|e| deserializer.attach_position(e));
+
+        (value, deserializer.into_inner())
+    }
+}
+
+impl<R: io::Read, T: DeserializeOwned, B: ByteOrder> Iterator for StreamDeserializer<R, T, B> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let next = match &mut self.source {
+            StreamSource::Bodies(deserializer) => Self::next_body(deserializer),
+            StreamSource::Documents { reader, options } => Self::next_document(reader, options),
+        };
+
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
 }
 
-struct SeqAccess<'a, R: Read> {
-    de: &'a mut Deserializer<R>,
+struct SeqAccess<'a, R, B> {
+    de: &'a mut Deserializer<R, B>,
     len: usize,
     current: usize,
+    /// The id shared by the elements of a packed sequence, which are stored without their ids.
+    packed: Option<u8>,
 }
 
-impl<'a, R: Read> SeqAccess<'a, R> {
-    fn new(de: &'a mut Deserializer<R>, len: usize) -> Self {
+impl<'a, R, B> SeqAccess<'a, R, B> {
+    fn new(de: &'a mut Deserializer<R, B>, len: usize) -> Self {
         Self {
             de,
             len,
             current: 0,
+            packed: None,
+        }
+    }
+
+    /// Reads the contents of a byte array as a sequence of `u8`.
+    fn bytes(de: &'a mut Deserializer<R, B>, len: usize) -> Self {
+        Self {
+            packed: Some(data_ids::U8_ID),
+            ..Self::new(de, len)
         }
     }
 }
 
-impl<'de, 'a, R: Read> serde::de::SeqAccess<'de> for SeqAccess<'a, R> {
+impl<'de, 'a, R: Read<'de>, B: ByteOrder> serde::de::SeqAccess<'de> for SeqAccess<'a, R, B> {
     type Error = Error;
 
     fn next_element_seed<T: serde::de::DeserializeSeed<'de>>(
@@ -474,7 +1353,16 @@ impl<'de, 'a, R: Read> serde::de::SeqAccess<'de> for SeqAccess<'a, R> {
     ) -> Result<Option<T::Value>, Self::Error> {
         if self.current < self.len {
             self.current += 1;
-            seed.deserialize(&mut *self.de).map(Some)
+            let packed = self.packed;
+            self.de
+                .with_segment(PathSegment::Index(self.current - 1), |de| {
+                    de.check_total_bytes(0)?;
+                    match packed {
+                        Some(id) => seed.deserialize(PackedElement { de, id }),
+                        None => seed.deserialize(de),
+                    }
+                })
+                .map(Some)
         } else {
             Ok(None)
         }
@@ -485,25 +1373,107 @@ impl<'de, 'a, R: Read> serde::de::SeqAccess<'de> for SeqAccess<'a, R> {
     }
 }
 
-struct MapAccess<'a, R: Read> {
-    de: &'a mut Deserializer<R>,
-    len: usize,
-    current_key: usize,
-    current_value: usize,
+/// Deserializes an element of a packed sequence, which is a numeric value without its id.
+struct PackedElement<'a, R, B> {
+    de: &'a mut Deserializer<R, B>,
+    id: u8,
 }
 
-impl<'a, R: Read> MapAccess<'a, R> {
-    fn new(de: &'a mut Deserializer<R>, len: usize) -> Self {
-        Self {
-            de,
-            len,
-            current_key: 0,
-            current_value: 0,
-        }
+impl<'de, 'a, R: Read<'de>, B: ByteOrder> serde::de::Deserializer<'de> for PackedElement<'a, R, B> {
+    type Error = Error;
+
+    fn deserialize_any<V: serde::de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (reader, encoding) = (&mut self.de.reader, self.de.encoding);
+        match self.id {
+            data_ids::I8_ID => visitor.visit_i8(reader.read_i8().map_err(Error::IoError)?),
+            data_ids::I16_ID => {
+                visitor.visit_i16(encoding.read_i16(reader).map_err(Error::IoError)?)
+            }
+            data_ids::I32_ID => {
+                visitor.visit_i32(encoding.read_i32(reader).map_err(Error::IoError)?)
+            }
+            data_ids::I64_ID => {
+                visitor.visit_i64(encoding.read_i64(reader).map_err(Error::IoError)?)
+            }
+            data_ids::I128_ID => {
+                visitor.visit_i128(encoding.read_i128(reader).map_err(Error::IoError)?)
+            }
+            data_ids::U8_ID => visitor.visit_u8(reader.read_u8().map_err(Error::IoError)?),
+            data_ids::U16_ID => {
+                visitor.visit_u16(encoding.read_u16(reader).map_err(Error::IoError)?)
+            }
+            data_ids::U32_ID => {
+                visitor.visit_u32(encoding.read_u32(reader).map_err(Error::IoError)?)
+            }
+            data_ids::U64_ID => {
+                visitor.visit_u64(encoding.read_u64(reader).map_err(Error::IoError)?)
+            }
+            data_ids::U128_ID => {
+                visitor.visit_u128(encoding.read_u128(reader).map_err(Error::IoError)?)
+            }
+            data_ids::F32_ID => {
+                visitor.visit_f32(encoding.read_f32(reader).map_err(Error::IoError)?)
+            }
+            data_ids::F64_ID => {
+                visitor.visit_f64(encoding.read_f64(reader).map_err(Error::IoError)?)
+            }
+            found => Err(Error::InvalidDataId {
+                expected: String::from("a numeric id"),
+                found,
+            }),
+        }
+    }
+
+    fn deserialize_option<V: serde::de::Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
     }
+
+    fn deserialize_newtype_struct<V: serde::de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct enum
+        identifier ignored_any
+    }
+}
+
+struct MapAccess<'a, R, B> {
+    de: &'a mut Deserializer<R, B>,
+    len: usize,
+    current_key: usize,
+    current_value: usize,
+    /// The fields of a struct, whose values are named after their field in the path. The values of other
+    /// maps are named by their position.
+    fields: &'static [&'static str],
+    field: Option<&'static str>,
 }
 
-impl<'de, 'a, R: Read> serde::de::MapAccess<'de> for MapAccess<'a, R> {
+impl<'a, R, B> MapAccess<'a, R, B> {
+    fn new(de: &'a mut Deserializer<R, B>, len: usize) -> Self {
+        Self {
+            de,
+            len,
+            current_key: 0,
+            current_value: 0,
+            fields: &[],
+            field: None,
+        }
+    }
+}
+
+impl<'de, 'a, R: Read<'de>, B: ByteOrder> serde::de::MapAccess<'de> for MapAccess<'a, R, B> {
     type Error = Error;
 
     fn next_key_seed<K: serde::de::DeserializeSeed<'de>>(
@@ -512,7 +1482,18 @@ impl<'de, 'a, R: Read> serde::de::MapAccess<'de> for MapAccess<'a, R> {
     ) -> Result<Option<K::Value>, Self::Error> {
         if self.current_key < self.len {
             self.current_key += 1;
-            seed.deserialize(&mut *self.de).map(Some)
+            if !self.fields.is_empty() {
+                self.de.key.fields = Some(self.fields);
+            }
+            let key = self
+                .de
+                .with_segment(PathSegment::Index(self.current_key - 1), |de| {
+                    de.check_total_bytes(0)?;
+                    seed.deserialize(de)
+                });
+            self.de.key.fields = None;
+            self.field = self.de.key.field.take();
+            key.map(Some)
         } else {
             Ok(None)
         }
@@ -524,31 +1505,108 @@ impl<'de, 'a, R: Read> serde::de::MapAccess<'de> for MapAccess<'a, R> {
     ) -> Result<V::Value, Self::Error> {
         if self.current_value < self.len {
             self.current_value += 1;
-            seed.deserialize(&mut *self.de)
+            let segment = match self.field.take() {
+                Some(field) => PathSegment::Field(field),
+                None => PathSegment::Index(self.current_value - 1),
+            };
+            self.de.with_segment(segment, |de| seed.deserialize(de))
         } else {
             Err(Error::InvalidMapAccess)
         }
     }
 }
 
-struct EnumAccess<'a, R: Read> {
-    de: &'a mut Deserializer<R>,
+/// Presents the values of a positional struct as a map keyed by the names of the fields.
+struct FieldAccess<'a, R, B> {
+    de: &'a mut Deserializer<R, B>,
+    fields: &'static [&'static str],
+    current: usize,
 }
 
-impl<'de, 'a, R: Read> serde::de::EnumAccess<'de> for EnumAccess<'a, R> {
+impl<'de, 'a, R: Read<'de>, B: ByteOrder> serde::de::MapAccess<'de> for FieldAccess<'a, R, B> {
+    type Error = Error;
+
+    fn next_key_seed<K: serde::de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.fields.get(self.current) {
+            Some(&field) => seed
+                .deserialize(BorrowedStrDeserializer::<Error>::new(field))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: serde::de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let field = *self
+            .fields
+            .get(self.current)
+            .ok_or(Error::InvalidMapAccess)?;
+        self.current += 1;
+        self.de.with_segment(PathSegment::Field(field), |de| {
+            de.check_total_bytes(0)?;
+            seed.deserialize(de)
+        })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.fields.len() - self.current)
+    }
+}
+
+struct EnumAccess<'a, R, B> {
+    de: &'a mut Deserializer<R, B>,
+    variants: &'static [&'static str],
+    /// Names the variant in the path once it has been read.
+    segment: PathSegment,
+}
+
+impl<'de, 'a, R: Read<'de>, B: ByteOrder> serde::de::EnumAccess<'de> for EnumAccess<'a, R, B> {
     type Error = Error;
     type Variant = Self;
 
     fn variant_seed<V: serde::de::DeserializeSeed<'de>>(
-        self,
+        mut self,
         seed: V,
     ) -> Result<(V::Value, Self::Variant), Self::Error> {
-        let val = seed.deserialize(&mut *self.de)?;
-        Ok((val, self))
+        match self.de.reader.read_u8().map_err(Error::IoError)? {
+            data_ids::ENUM_VARIANT_ID => {
+                let index = self
+                    .de
+                    .encoding
+                    .read_u32(&mut self.de.reader)
+                    .map_err(Error::IoError)?;
+                let name = self.variants.get(index as usize).copied();
+                self.segment = PathSegment::Variant(index, name);
+                let val = seed.deserialize(U32Deserializer::<Error>::new(index))?;
+                Ok((val, self))
+            }
+            data_ids::NAMED_ENUM_VARIANT_ID => {
+                let name = String::deserialize(&mut *self.de)?;
+                // Other names can only be read as a unit variant, which has no payload that can fail.
+                if let Some(index) = self.variants.iter().position(|v| *v == name) {
+                    self.segment = PathSegment::Variant(index as u32, Some(self.variants[index]));
+                }
+                let val = seed.deserialize(StringDeserializer::<Error>::new(name))?;
+                Ok((val, self))
+            }
+            found => Err(Error::InvalidDataId {
+                expected: format!(
+                    "{} or {}",
+                    data_ids::ENUM_VARIANT_ID,
+                    data_ids::NAMED_ENUM_VARIANT_ID
+                ),
+                found,
+            }),
+        }
     }
 }
 
-impl<'de, 'a, R: Read> serde::de::VariantAccess<'de> for EnumAccess<'a, R> {
+impl<'de, 'a, R: Read<'de>, B: ByteOrder> serde::de::VariantAccess<'de> for EnumAccess<'a, R, B> {
     type Error = Error;
 
     fn unit_variant(self) -> Result<(), Self::Error> {
@@ -559,7 +1617,8 @@ impl<'de, 'a, R: Read> serde::de::VariantAccess<'de> for EnumAccess<'a, R> {
         self,
         seed: T,
     ) -> Result<T::Value, Self::Error> {
-        seed.deserialize(&mut *self.de)
+        self.de
+            .with_segment(self.segment, |de| seed.deserialize(de))
     }
 
     fn tuple_variant<V: serde::de::Visitor<'de>>(
@@ -567,29 +1626,225 @@ impl<'de, 'a, R: Read> serde::de::VariantAccess<'de> for EnumAccess<'a, R> {
         len: usize,
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        let length = self.de.0.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
-        if length != len {
-            return Err(Error::InvalidLength {
-                expected: len,
-                actual: length,
-                message: String::from("Invalid tuple variant length"),
-            });
+        self.de.with_segment(self.segment, |de| {
+            if de.header.version() >= FormatVersion::V2 {
+                read_id(&mut de.reader, data_ids::TUPLE_ID)?;
+            }
+            let length = de.read_collection_length()?;
+            if length != len {
+                Err(Error::InvalidLength {
+                    expected: len,
+                    actual: length,
+                    message: String::from("Invalid tuple variant length"),
+                })
+            } else {
+                visitor.visit_seq(SeqAccess::new(de, length))
+            }
+        })
+    }
+
+    fn struct_variant<V: serde::de::Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.de.with_segment(self.segment, |de| {
+            // Version 1 files store the length of the fields directly after the variant index.
+            if de.header.version() == FormatVersion::V1 {
+                return de.read_struct(false, fields, visitor);
+            }
+
+            let positional = de.read_struct_id()?;
+            de.read_struct(positional, fields, visitor)
+        })
+    }
+}
+
+/// Presents an enum variant to `deserialize_any` as a map with a single entry from the variant index to
+/// its payload, which is the form serde uses when buffering untagged and internally tagged enums.
+struct VariantMapAccess<'a, R, B> {
+    de: &'a mut Deserializer<R, B>,
+    /// The index of the variant, or `None` if its name is read from the input.
+    variant: Option<u32>,
+    key_read: bool,
+    is_unit: bool,
+}
+
+impl<'de, 'a, R: Read<'de>, B: ByteOrder> serde::de::MapAccess<'de> for VariantMapAccess<'a, R, B> {
+    type Error = Error;
+
+    fn next_key_seed<K: serde::de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        if std::mem::replace(&mut self.key_read, true) {
+            return Ok(None);
+        }
+
+        match self.variant {
+            Some(variant) => seed
+                .deserialize(U64Deserializer::new(variant as u64))
+                .map(Some),
+            None => seed.deserialize(&mut *self.de).map(Some),
+        }
+    }
+
+    fn next_value_seed<V: serde::de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        if self.is_unit {
+            seed.deserialize(UnitDeserializer::new())
         } else {
-            visitor.visit_seq(SeqAccess::new(&mut *self.de, length))
+            seed.deserialize(&mut *self.de)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(1)
+    }
+}
+
+/// Hands the data id of the next value to [`crate::Value`] so it can be rebuilt without losing
+/// the distinctions that `deserialize_any` cannot express, such as tuples versus sequences.
+struct ValueAccess<'a, R, B> {
+    de: &'a mut Deserializer<R, B>,
+}
+
+impl<'de, 'a, R: Read<'de>, B: ByteOrder> serde::de::EnumAccess<'de> for ValueAccess<'a, R, B> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: serde::de::DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self::Variant), Self::Error> {
+        let id = self.de.reader.peek_u8().map_err(Error::IoError)?;
+        let val = seed.deserialize(U8Deserializer::<Error>::new(id))?;
+        Ok((val, self))
+    }
+}
+
+impl<'de, 'a, R: Read<'de>, B: ByteOrder> serde::de::VariantAccess<'de> for ValueAccess<'a, R, B> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        read_id(&mut self.de.reader, data_ids::NULL_ID)
+    }
+
+    fn newtype_variant_seed<T: serde::de::DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, Self::Error> {
+        match self.de.reader.peek_u8().map_err(Error::IoError)? {
+            data_ids::UNIT_VARIANT_ID => {
+                self.de.reader.read_u8().map_err(Error::IoError)?;
+                let variant = self
+                    .de
+                    .encoding
+                    .read_u32(&mut self.de.reader)
+                    .map_err(Error::IoError)?;
+                seed.deserialize(U32Deserializer::new(variant))
+            }
+            data_ids::NAMED_UNIT_VARIANT_ID => {
+                self.de.reader.read_u8().map_err(Error::IoError)?;
+                seed.deserialize(&mut *self.de)
+            }
+            _ => seed.deserialize(&mut *self.de),
         }
     }
 
+    fn tuple_variant<V: serde::de::Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        let variant = match self.de.reader.read_u8().map_err(Error::IoError)? {
+            data_ids::ENUM_VARIANT_ID => {
+                let variant = self
+                    .de
+                    .encoding
+                    .read_u32(&mut self.de.reader)
+                    .map_err(Error::IoError)?;
+                self.de.check_untyped_variant(variant)?;
+                Some(variant)
+            }
+            data_ids::NAMED_ENUM_VARIANT_ID => None,
+            found => {
+                return Err(Error::InvalidDataId {
+                    expected: format!(
+                        "{} or {}",
+                        data_ids::ENUM_VARIANT_ID,
+                        data_ids::NAMED_ENUM_VARIANT_ID
+                    ),
+                    found,
+                })
+            }
+        };
+
+        visitor.visit_seq(VariantValueAccess {
+            de: &mut *self.de,
+            variant,
+            remaining: 2,
+        })
+    }
+
     fn struct_variant<V: serde::de::Visitor<'de>>(
         self,
-        _fields: &'static [&'static str],
+        fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value, Self::Error> {
-        let length = self.de.0.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
-        visitor.visit_map(MapAccess::new(&mut *self.de, length))
+        self.tuple_variant(fields.len(), visitor)
     }
 }
 
-fn read_id<R: Read>(reader: &mut R, expected: u8) -> Result<(), Error> {
+/// Yields the variant index or name and then the payload of an enum variant as a two element sequence.
+struct VariantValueAccess<'a, R, B> {
+    de: &'a mut Deserializer<R, B>,
+    /// The index of the variant, or `None` if its name is read from the input.
+    variant: Option<u32>,
+    remaining: usize,
+}
+
+impl<'de, 'a, R: Read<'de>, B: ByteOrder> serde::de::SeqAccess<'de>
+    for VariantValueAccess<'a, R, B>
+{
+    type Error = Error;
+
+    fn next_element_seed<T: serde::de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Self::Error> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+
+        self.remaining -= 1;
+        match self.variant.take() {
+            Some(variant) => seed.deserialize(U32Deserializer::new(variant)).map(Some),
+            None => seed.deserialize(&mut *self.de).map(Some),
+        }
+    }
+}
+
+/// Finds the field named by the key of a struct entry while it is read, so that errors in its value can
+/// name the field without the key being copied.
+#[derive(Default)]
+struct KeyCapture {
+    /// The fields of the struct whose key is being read.
+    fields: Option<&'static [&'static str]>,
+    field: Option<&'static str>,
+}
+
+impl KeyCapture {
+    fn record(&mut self, key: &str) {
+        if let Some(fields) = self.fields.take() {
+            self.field = fields.iter().copied().find(|field| *field == key);
+        }
+    }
+}
+
+fn read_id<R: io::Read>(reader: &mut R, expected: u8) -> Result<(), Error> {
     let found = reader.read_u8().map_err(Error::IoError)?;
     if found == expected {
         Ok(())
@@ -603,26 +1858,80 @@ fn read_id<R: Read>(reader: &mut R, expected: u8) -> Result<(), Error> {
 
 #[cfg(test)]
 mod tests {
-    use std::fmt::Debug;
+    use std::{borrow::Cow, collections::HashMap, fmt::Debug};
 
+    use byteorder::ByteOrder;
     use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-    use crate::{se::to_bytes, Compression};
+    use crate::{
+        data_ids, se::to_bytes, Checksum, Compression, Deserializer, DeserializerOptions,
+        Endianness, EnumEncoding, Error, FileHeader, FormatVersion, Limit, LittleEndian,
+        SbifOptions, Serializer, SerializerOptions, StreamDeserializer, StructEncoding, Value,
+    };
 
     fn deserialization_test_base<T: Serialize + DeserializeOwned + PartialEq + Debug>(
         value: &T,
         compression: Compression,
+        version: Option<FormatVersion>,
     ) {
-        let serialized = to_bytes(&value, compression).unwrap();
+        let plain = SerializerOptions {
+            pack_sequences: false,
+            version,
+            ..SerializerOptions::default()
+        };
+        let varint = SerializerOptions {
+            varint_encoding: true,
+            ..SerializerOptions::default()
+        };
+        // Version 2 packs sequences by default.
+        let packed = SerializerOptions {
+            version: Some(FormatVersion::V2),
+            ..SerializerOptions::default()
+        };
+        for options in [plain, varint, packed] {
+            let serializer =
+                Serializer::with_options(Vec::new(), compression, options.clone()).unwrap();
+            round_trip(value, serializer);
+            let serializer =
+                Serializer::<_, LittleEndian>::with_byte_order(Vec::new(), compression, options)
+                    .unwrap();
+            round_trip(value, serializer);
+        }
+    }
+
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug, B: ByteOrder>(
+        value: &T,
+        mut serializer: Serializer<Vec<u8>, B>,
+    ) {
+        value.serialize(&mut serializer).unwrap();
+        let serialized = serializer.finish().unwrap();
         let deserialized: T = crate::de::from_slice(&serialized).unwrap();
         assert_eq!(value, &deserialized);
     }
 
+    /// Serializes `value` into an uncompressed file with the given options.
+    fn options_serialization<T: Serialize + ?Sized>(
+        value: &T,
+        options: SerializerOptions,
+    ) -> Vec<u8> {
+        let mut serializer =
+            Serializer::with_options(Vec::new(), Compression::None, options).unwrap();
+        value.serialize(&mut serializer).unwrap();
+        serializer.finish().unwrap()
+    }
+
     fn deserialization_test<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: T) {
-        deserialization_test_base(&value, Compression::None);
-        deserialization_test_base(&value, Compression::Deflate(6));
-        deserialization_test_base(&value, Compression::GZip(6));
-        deserialization_test_base(&value, Compression::ZLib(6));
+        for compression in compressions() {
+            deserialization_test_base(&value, compression, None);
+        }
+    }
+
+    /// Like [`deserialization_test`] for values that version 1 cannot hold, such as 128-bit integers, or
+    /// whose tuple and struct variants are read through `deserialize_any`.
+    fn v2_deserialization_test<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: T) {
+        for compression in compressions() {
+            deserialization_test_base(&value, compression, Some(FormatVersion::V2));
+        }
     }
 
     #[test]
@@ -641,6 +1950,8 @@ mod tests {
         deserialization_test(100_u16);
         deserialization_test(100_u32);
         deserialization_test(100_u64);
+        v2_deserialization_test(i128::MIN);
+        v2_deserialization_test(u128::MAX);
     }
 
     #[test]
@@ -674,6 +1985,285 @@ mod tests {
         deserialization_test((0..10).collect::<Vec<_>>());
     }
 
+    #[test]
+    fn test_packed_seq_deserialization() {
+        deserialization_test(vec![1.5_f32, -2.0, 3.25]);
+        v2_deserialization_test(vec![i128::MIN, 0, i128::MAX]);
+        deserialization_test(vec![Some(1_u16), Some(2)]);
+        deserialization_test(vec![vec![1_u8, 2], vec![3]]);
+        deserialization_test(vec![Value::U8(1), Value::U16(2)]);
+        deserialization_test(Value::Seq(vec![Value::F64(1.0), Value::F64(2.0)]));
+
+        let options = SbifOptions::new()
+            .compression(Compression::None)
+            .version(FormatVersion::V2);
+        let serialized = crate::to_bytes_with(&vec![1_u8, 2, 3], &options).unwrap();
+        assert_eq!(serialized[9], data_ids::PACKED_SEQ_ID);
+        assert_eq!(
+            crate::de::from_slice::<Vec<u64>>(&serialized).unwrap(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn test_u8_sequences_as_bytes_deserialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Texture {
+            pixels: Vec<u8>,
+            size: [u8; 2],
+            mixed: (u8, u16),
+        }
+
+        let texture = Texture {
+            pixels: vec![255, 0, 128, 64],
+            size: [2, 2],
+            mixed: (1, 2),
+        };
+
+        let options = SbifOptions::new()
+            .compression(Compression::None)
+            .u8_sequences_as_bytes(true);
+        let serialized = crate::to_bytes_with(&texture, &options).unwrap();
+        assert_eq!(
+            crate::de::from_slice::<Texture>(&serialized).unwrap(),
+            texture
+        );
+
+        // Untagged enums and flattened structs read the bytes through `deserialize_any`, and serde does not
+        // turn buffered bytes back into a sequence.
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        #[serde(untagged)]
+        enum Untagged {
+            Pixels(Vec<u8>),
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Flattened {
+            #[serde(flatten)]
+            texture: Texture,
+        }
+
+        let untagged = Untagged::Pixels(vec![255, 0]);
+        let flattened = Flattened { texture };
+        let serialized = crate::to_bytes_with(&untagged, &options).unwrap();
+        assert!(crate::de::from_slice::<Untagged>(&serialized).is_err());
+        let serialized = crate::to_bytes_with(&flattened, &options).unwrap();
+        assert!(crate::de::from_slice::<Flattened>(&serialized).is_err());
+
+        let serialized = to_bytes(&untagged, Compression::None).unwrap();
+        assert_eq!(
+            crate::de::from_slice::<Untagged>(&serialized).unwrap(),
+            untagged
+        );
+        let serialized = to_bytes(&flattened, Compression::None).unwrap();
+        assert_eq!(
+            crate::de::from_slice::<Flattened>(&serialized).unwrap(),
+            flattened
+        );
+
+        let serialized = to_bytes(&Value::Bytes(vec![1, 2, 3]), Compression::None).unwrap();
+        assert_eq!(
+            crate::de::from_slice::<Vec<u8>>(&serialized).unwrap(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            crate::de::from_slice::<[u8; 3]>(&serialized).unwrap(),
+            [1, 2, 3]
+        );
+        assert!(matches!(
+            crate::de::from_slice::<[u8; 2]>(&serialized).map_err(Error::into_inner),
+            Err(Error::InvalidLength { .. })
+        ));
+    }
+
+    #[test]
+    fn test_interned_key_deserialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Player {
+            name: String,
+            inventory: HashMap<String, u8>,
+        }
+
+        let players = vec![
+            Player {
+                name: String::from("a"),
+                inventory: HashMap::from([(String::from("sword"), 1), (String::from("shield"), 2)]),
+            },
+            Player {
+                name: String::from("b"),
+                inventory: HashMap::from([(String::from("sword"), 3)]),
+            },
+        ];
+
+        let options = SerializerOptions {
+            intern_keys: true,
+            ..SerializerOptions::default()
+        };
+        let serialized = options_serialization(&players, options);
+
+        assert_eq!(
+            crate::de::from_slice::<Vec<Player>>(&serialized).unwrap(),
+            players
+        );
+        let value = crate::de::from_slice::<Value>(&serialized).unwrap();
+        assert_eq!(
+            crate::value::from_value::<Vec<Player>>(value).unwrap(),
+            players
+        );
+
+        let mut invalid = v2_header();
+        invalid.extend([
+            data_ids::MAP_ID,
+            0,
+            0,
+            0,
+            1,
+            data_ids::STR_REF_ID,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        invalid.extend([data_ids::U8_ID, 1]);
+        assert!(matches!(
+            crate::de::from_slice::<HashMap<String, u8>>(&invalid).map_err(Error::into_inner),
+            Err(Error::InvalidStringReference {
+                index: 0,
+                interned: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn test_positional_struct_deserialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Position {
+            x: f32,
+            y: f32,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Message {
+            Move { id: u32, to: Position },
+        }
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Extended {
+            x: f32,
+            y: f32,
+            z: f32,
+        }
+
+        let options = SerializerOptions {
+            struct_encoding: StructEncoding::Positional,
+            ..SerializerOptions::default()
+        };
+        let message = Message::Move {
+            id: 7,
+            to: Position { x: 1.0, y: -2.5 },
+        };
+        let serialized = options_serialization(&message, options.clone());
+        assert_eq!(
+            crate::de::from_slice::<Message>(&serialized).unwrap(),
+            message
+        );
+
+        let serialized = options_serialization(&Position { x: 1.0, y: -2.5 }, options);
+        let error = crate::de::from_slice::<Extended>(&serialized)
+            .map_err(Error::into_inner)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            Error::InvalidLength {
+                expected: 3,
+                actual: 2,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_named_variant_deserialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Old {
+            A,
+            B(u8),
+            C(u8, u8),
+            D { x: u8 },
+            E,
+        }
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        enum New {
+            D {
+                x: u8,
+            },
+            C(u8, u8),
+            Inserted,
+            B(u8),
+            A,
+            #[serde(other)]
+            Unknown,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        #[serde(untagged)]
+        enum Untagged {
+            Old(Old),
+        }
+
+        let options = SerializerOptions {
+            enum_encoding: EnumEncoding::Name,
+            ..SerializerOptions::default()
+        };
+
+        let values = vec![Old::A, Old::B(1), Old::C(2, 3), Old::D { x: 4 }, Old::E];
+        let serialized = options_serialization(&values, options.clone());
+        assert_eq!(
+            crate::de::from_slice::<Vec<Old>>(&serialized).unwrap(),
+            values
+        );
+        assert_eq!(
+            crate::de::from_slice::<Vec<New>>(&serialized).unwrap(),
+            vec![
+                New::A,
+                New::B(1),
+                New::C(2, 3),
+                New::D { x: 4 },
+                New::Unknown
+            ]
+        );
+
+        let value = crate::de::from_slice::<Value>(&serialized).unwrap();
+        assert_eq!(
+            value,
+            Value::Seq(vec![
+                Value::NamedUnitVariant(String::from("A")),
+                Value::NamedEnumVariant(String::from("B"), Box::new(Value::U8(1))),
+                Value::NamedEnumVariant(
+                    String::from("C"),
+                    Box::new(Value::Tuple(vec![Value::U8(2), Value::U8(3)]))
+                ),
+                Value::NamedEnumVariant(
+                    String::from("D"),
+                    Box::new(Value::Map(vec![(
+                        Value::Str(String::from("x")),
+                        Value::U8(4)
+                    )]))
+                ),
+                Value::NamedUnitVariant(String::from("E")),
+            ])
+        );
+        assert_eq!(options_serialization(&value, options.clone()), serialized);
+
+        let untagged = vec![Untagged::Old(Old::A), Untagged::Old(Old::D { x: 5 })];
+        let serialized = options_serialization(&untagged, options);
+        assert_eq!(
+            crate::de::from_slice::<Vec<Untagged>>(&serialized).unwrap(),
+            untagged
+        );
+    }
+
     #[test]
     fn test_tuple_deserialization() {
         deserialization_test((0_u8, 'a', "Hello World!".to_string()));
@@ -707,6 +2297,40 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_unknown_length_deserialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Inner {
+            b: u8,
+            c: Vec<String>,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Flattened {
+            a: u8,
+            #[serde(flatten)]
+            inner: Inner,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Outer {
+            #[serde(flatten)]
+            flattened: Flattened,
+            d: Option<u16>,
+        }
+
+        deserialization_test(Outer {
+            flattened: Flattened {
+                a: 1,
+                inner: Inner {
+                    b: 2,
+                    c: vec!["Hello".to_string(), "World!".to_string()],
+                },
+            },
+            d: Some(3),
+        });
+    }
+
     #[test]
     fn test_enum_deserialization() {
         #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -727,9 +2351,1040 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_borrowed_deserialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Borrowed<'a> {
+            name: &'a str,
+            #[serde(borrow)]
+            nested: Vec<&'a str>,
+        }
+
+        let value = Borrowed {
+            name: "Hello",
+            nested: vec!["World", "!"],
+        };
+        let serialized = to_bytes(&value, Compression::None).unwrap();
+        let deserialized: Borrowed = crate::de::from_slice(&serialized).unwrap();
+        assert_eq!(value, deserialized);
+        assert!(serialized
+            .as_ptr_range()
+            .contains(&deserialized.name.as_ptr()));
+
+        let serialized =
+            to_bytes(&Value::Bytes(b"Hello World!".to_vec()), Compression::None).unwrap();
+        let deserialized: &[u8] = crate::de::from_slice(&serialized).unwrap();
+        assert_eq!(deserialized, b"Hello World!");
+        assert!(serialized.as_ptr_range().contains(&deserialized.as_ptr()));
+    }
+
+    #[test]
+    fn test_borrowed_deserialization_requires_uncompressed_data() {
+        let serialized = to_bytes(&"Hello World!", Compression::GZip(6)).unwrap();
+        assert!(crate::de::from_slice::<&str>(&serialized).is_err());
+        assert_eq!(
+            crate::de::from_slice::<String>(&serialized).unwrap(),
+            "Hello World!"
+        );
+    }
+
+    #[test]
+    fn test_header_versions() {
+        let mut header = v1_header();
+        header.extend([data_ids::U16_ID, 1, 0]);
+        assert_eq!(crate::de::from_slice::<u16>(&header).unwrap(), 256);
+
+        let mut header = v2_header();
+        assert_eq!(header[6], 2);
+        header.extend([data_ids::U16_ID, 1, 0]);
+        assert_eq!(crate::de::from_slice::<u16>(&header).unwrap(), 256);
+
+        header[8] = 0x01;
+        header.truncate(9);
+        header.extend([data_ids::U16_ID, 0x80, 0x02]);
+        assert_eq!(crate::de::from_slice::<u16>(&header).unwrap(), 256);
+
+        header[8] = 0x02;
+        header.truncate(9);
+        header.extend([data_ids::U16_ID, 0, 1]);
+        assert_eq!(crate::de::from_slice::<u16>(&header).unwrap(), 256);
+
+        header[8] = 0x80;
+        assert!(matches!(
+            crate::de::from_slice::<u16>(&header),
+            Err(Error::UnsupportedFlags(0x80))
+        ));
+
+        header[6] = u8::from(FormatVersion::LATEST) + 1;
+        assert!(matches!(
+            crate::de::from_slice::<u16>(&header),
+            Err(Error::InvalidVersion { .. })
+        ));
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum GoldenShape {
+        Circle(f32),
+        Rect { width: u16, height: u16 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Golden {
+        name: String,
+        id: u64,
+        scores: Vec<i32>,
+        ratio: f64,
+        tag: Option<(char, bool)>,
+        shapes: Vec<GoldenShape>,
+    }
+
+    fn golden_options(version: FormatVersion) -> SerializerOptions {
+        // The golden files were written before sequences were packed by default.
+        let options = SerializerOptions {
+            pack_sequences: false,
+            ..SerializerOptions::default()
+        };
+        match version {
+            FormatVersion::V1 => SerializerOptions {
+                version: Some(FormatVersion::V1),
+                ..options
+            },
+            FormatVersion::V2 => SerializerOptions {
+                varint_encoding: true,
+                ..options
+            },
+            FormatVersion::V3 => SerializerOptions {
+                metadata: Some(crate::Metadata {
+                    app_id: String::from("golden"),
+                    schema_version: 1,
+                    created: None,
+                    entries: [(String::from("key"), String::from("value"))].into(),
+                }),
+                ..options
+            },
+            FormatVersion::V4 => SerializerOptions {
+                version: Some(FormatVersion::V4),
+                ..options
+            },
+        }
+    }
+
+    fn golden() -> Golden {
+        Golden {
+            name: String::from("Golden"),
+            id: 1 << 40,
+            scores: vec![-1, 0, 300],
+            ratio: 0.25,
+            tag: Some(('g', true)),
+            shapes: vec![
+                GoldenShape::Circle(1.5),
+                GoldenShape::Rect {
+                    width: 3,
+                    height: 4,
+                },
+            ],
+        }
+    }
+
+    /// Files written by each version of the writer, which must stay readable and be written identically.
+    #[test]
+    fn test_golden_files() {
+        let golden = golden();
+
+        let files: &[(FormatVersion, &[u8])] = &[
+            (FormatVersion::V1, include_bytes!("../tests/golden/v1.sbif")),
+            (FormatVersion::V2, include_bytes!("../tests/golden/v2.sbif")),
+            (FormatVersion::V3, include_bytes!("../tests/golden/v3.sbif")),
+            (FormatVersion::V4, include_bytes!("../tests/golden/v4.sbif")),
+        ];
+        for &(version, file) in files {
+            let deserializer = Deserializer::from_slice(file).unwrap();
+            assert_eq!(deserializer.header().version(), version);
+            assert_eq!(crate::de::from_slice::<Golden>(file).unwrap(), golden);
+
+            let serialized = options_serialization(&golden, golden_options(version));
+            assert_eq!(serialized, file, "{:?}", version);
+        }
+    }
+
+    /// Checks a version 4 golden file with a checksum, whose body ends with the checksum id and the big endian
+    /// hash, and returns the body and the hash.
+    #[cfg(any(feature = "crc32", feature = "xxh3"))]
+    fn checksum_golden_test(checksum: Checksum, file: &[u8]) -> (&[u8], &[u8]) {
+        let options = SerializerOptions {
+            pack_sequences: false,
+            checksum: Some(checksum),
+            ..SerializerOptions::default()
+        };
+        assert_eq!(crate::de::from_slice::<Golden>(file).unwrap(), golden());
+
+        assert_eq!(options_serialization(&golden(), options), file);
+
+        // The body follows the 9 byte header.
+        let (body, checksum_bytes) = file[9..].split_at(file.len() - 10 - checksum_len(checksum));
+        assert_eq!(checksum_bytes[0], data_ids::CHECKSUM_ID);
+        (body, &checksum_bytes[1..])
+    }
+
+    /// The version 4 golden file with a CRC32 checksum.
+    #[cfg(feature = "crc32")]
+    #[test]
+    fn test_crc32_golden_file() {
+        let file: &[u8] = include_bytes!("../tests/golden/v4-crc32.sbif");
+        let (body, hash) = checksum_golden_test(Checksum::Crc32, file);
+        assert_eq!(hash, crc32fast::hash(body).to_be_bytes());
+    }
+
+    /// The version 4 golden file with an xxHash3 checksum.
+    #[cfg(feature = "xxh3")]
+    #[test]
+    fn test_xxh3_golden_file() {
+        let file: &[u8] = include_bytes!("../tests/golden/v4-xxh3.sbif");
+        let (body, hash) = checksum_golden_test(Checksum::Xxh3, file);
+        assert_eq!(hash, xxhash_rust::xxh3::xxh3_64(body).to_be_bytes());
+    }
+
+    #[test]
+    fn test_read_header() {
+        let mut serializer = Serializer::<_, LittleEndian>::with_byte_order(
+            Vec::new(),
+            Compression::ZLib(3),
+            SerializerOptions::default(),
+        )
+        .unwrap();
+        1_u8.serialize(&mut serializer).unwrap();
+        let serialized = serializer.finish().unwrap();
+
+        let mut reader = serialized.as_slice();
+        let header = crate::read_header(&mut reader).unwrap();
+        assert_eq!(header.name(), "SBIF");
+        assert_eq!(header.version(), FormatVersion::V2);
+        assert_eq!(header.compression(), Compression::ZLib(3));
+        assert_eq!(header.endianness(), Endianness::Little);
+        assert!(!header.varint_encoding());
+        assert_eq!(reader.len(), serialized.len() - 13);
+
+        let deserializer = Deserializer::<_, LittleEndian>::with_byte_order(
+            serialized.as_slice(),
+            DeserializerOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(deserializer.header(), &header);
+        let deserializer = Deserializer::<_, LittleEndian>::from_slice_with_byte_order(
+            &serialized,
+            DeserializerOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(deserializer.header(), &header);
+        assert!(matches!(
+            Deserializer::new(serialized.as_slice()),
+            Err(Error::ByteOrderMismatch {
+                expected: Endianness::Big,
+                found: Endianness::Little
+            })
+        ));
+        assert!(Deserializer::from_slice(&serialized).is_err());
+
+        let mut invalid = FileHeader::new(Compression::None);
+        invalid.header_name = String::from("ABCD");
+        assert!(matches!(
+            crate::read_header(invalid.to_bytes().unwrap().as_slice()),
+            Err(Error::InvalidHeader { expected, found }) if expected == "'SBIF'" && found == "ABCD"
+        ));
+
+        // The name is checked before the version and compression, which other formats may not have.
+        let mut other_format = invalid.to_bytes().unwrap();
+        other_format[6] = 0xFF;
+        other_format[7] = 0xFF;
+        assert!(matches!(
+            crate::read_header(other_format.as_slice()),
+            Err(Error::InvalidHeader { .. })
+        ));
+    }
+
+    #[test]
+    fn test_custom_magic() {
+        let options = SerializerOptions {
+            magic: Cow::Borrowed("MYGAME-SAVE"),
+            ..SerializerOptions::default()
+        };
+        let serialized = options_serialization("save", options);
+
+        let error = crate::de::from_slice::<String>(&serialized).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid sbif header: expected 'SBIF', found 'MYGAME-SAVE'"
+        );
+
+        let options = DeserializerOptions {
+            accepted_magics: vec![
+                Cow::Borrowed("SBIF"),
+                Cow::Owned(String::from("MYGAME-SAVE")),
+            ],
+            ..DeserializerOptions::default()
+        };
+        let header =
+            crate::read_header_with(serialized.as_slice(), &options.accepted_magics).unwrap();
+        assert_eq!(header.name(), "MYGAME-SAVE");
+        let mut deserializer = Deserializer::from_slice_with_options(&serialized, options).unwrap();
+        assert_eq!(deserializer.header(), &header);
+        assert_eq!(String::deserialize(&mut deserializer).unwrap(), "save");
+
+        let options = DeserializerOptions {
+            accepted_magics: vec![Cow::Borrowed("A"), Cow::Borrowed("B")],
+            ..DeserializerOptions::default()
+        };
+        assert!(matches!(
+            Deserializer::with_options(serialized.as_slice(), options),
+            Err(Error::InvalidHeader { expected, .. }) if expected == "'A' or 'B'"
+        ));
+        assert!(matches!(
+            crate::read_header_with(serialized.as_slice(), &["SBIF"]),
+            Err(Error::InvalidHeader { found, .. }) if found == "MYGAME-SAVE"
+        ));
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn test_unsupported_zstd_compression() {
+        let mut serialized = v2_header();
+        serialized[7] = 4;
+        serialized.extend([0, 0, 0, 3]);
+        assert!(matches!(
+            crate::de::from_slice::<u8>(&serialized),
+            Err(crate::Error::UnsupportedCompression("zstd"))
+        ));
+    }
+
+    #[cfg(not(feature = "crc32"))]
+    #[test]
+    fn test_unsupported_crc32_checksum() {
+        let mut serialized = v2_header();
+        serialized[6] = 4;
+        serialized[8] = 0x08;
+        assert!(matches!(
+            crate::read_header(serialized.as_slice()),
+            Err(crate::Error::UnsupportedChecksum("crc32"))
+        ));
+    }
+
+    #[cfg(not(feature = "xxh3"))]
+    #[test]
+    fn test_unsupported_xxh3_checksum() {
+        let mut serialized = v2_header();
+        serialized[6] = 4;
+        serialized[8] = 0x10;
+        assert!(matches!(
+            crate::read_header(serialized.as_slice()),
+            Err(crate::Error::UnsupportedChecksum("xxh3"))
+        ));
+    }
+
+    #[cfg(not(feature = "lz4"))]
+    #[test]
+    fn test_unsupported_lz4_compression() {
+        let mut serialized = v2_header();
+        serialized[7] = 5;
+        assert!(matches!(
+            crate::de::from_slice::<u8>(&serialized),
+            Err(crate::Error::UnsupportedCompression("lz4"))
+        ));
+    }
+
+    #[test]
+    fn test_legacy_variant_deserialization() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        enum TestEnum {
+            Unit,
+            Tuple(u8, u8),
+            Struct { a: u8 },
+        }
+
+        let mut tuple = v1_header();
+        tuple.extend([data_ids::ENUM_VARIANT_ID, 0, 0, 0, 1, 0, 0, 0, 2]);
+        tuple.extend([data_ids::U8_ID, 1, data_ids::U8_ID, 2]);
+        assert_eq!(
+            crate::de::from_slice::<TestEnum>(&tuple).unwrap(),
+            TestEnum::Tuple(1, 2)
+        );
+
+        let mut structure = v1_header();
+        structure.extend([data_ids::ENUM_VARIANT_ID, 0, 0, 0, 2, 0, 0, 0, 1]);
+        structure.extend([data_ids::STR_ID, 0, 0, 0, 1, 97, data_ids::U8_ID, 1]);
+        assert_eq!(
+            crate::de::from_slice::<TestEnum>(&structure).unwrap(),
+            TestEnum::Struct { a: 1 }
+        );
+
+        // Later versions require the fields to be wrapped in a tuple or map.
+        let mut nested = v2_header();
+        nested.extend(&structure[8..]);
+        assert!(crate::de::from_slice::<TestEnum>(&nested).is_err());
+    }
+
+    #[test]
+    fn test_legacy_variant_any_deserialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Legacy {
+            Unit(()),
+            Newtype(u8),
+            Tuple(String, u8),
+        }
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        #[serde(untagged)]
+        enum Untagged {
+            Legacy(Legacy),
+        }
+
+        let options = SbifOptions::new()
+            .compression(Compression::None)
+            .version(FormatVersion::V1);
+        // The unit payload and the number of fields of the tuple variant both start with a zero byte.
+        let unit = (Legacy::Unit(()), ());
+        let unit_bytes = crate::to_bytes_with(&unit, &options).unwrap();
+        let tuple = Legacy::Tuple(String::from("s"), 1);
+        let tuple_bytes = crate::to_bytes_with(&tuple, &options).unwrap();
+        assert_eq!(
+            crate::de::from_slice::<(Legacy, ())>(&unit_bytes).unwrap(),
+            unit
+        );
+        assert_eq!(
+            crate::de::from_slice::<Legacy>(&tuple_bytes).unwrap(),
+            tuple
+        );
+
+        assert!(matches!(
+            crate::de::from_slice::<Value>(&unit_bytes).map_err(Error::into_inner),
+            Err(Error::UntypedVariant(0))
+        ));
+        assert!(matches!(
+            crate::de::from_slice::<Value>(&tuple_bytes).map_err(Error::into_inner),
+            Err(Error::UntypedVariant(2))
+        ));
+        assert!(matches!(
+            crate::de::from_slice::<Untagged>(&tuple_bytes).map_err(Error::into_inner),
+            Err(Error::UntypedVariant(2))
+        ));
+
+        // Payloads that start with any other data id are newtype variants.
+        let newtype_bytes = crate::to_bytes_with(&Legacy::Newtype(5), &options).unwrap();
+        let value = crate::de::from_slice::<Value>(&newtype_bytes).unwrap();
+        assert_eq!(value, Value::EnumVariant(1, Box::new(Value::U8(5))));
+        assert_eq!(
+            crate::to_bytes_with(&value, &options).unwrap(),
+            newtype_bytes
+        );
+        assert_eq!(
+            crate::de::from_slice::<Untagged>(&newtype_bytes).unwrap(),
+            Untagged::Legacy(Legacy::Newtype(5))
+        );
+    }
+
+    fn v1_header() -> Vec<u8> {
+        FileHeader::new(Compression::None).to_bytes().unwrap()
+    }
+
+    /// A version 2 header without flags, whose flags byte at index 8 tests can change.
+    fn v2_header() -> Vec<u8> {
+        let mut header = FileHeader::new(Compression::None);
+        header.version = FormatVersion::V2;
+        header.to_bytes().unwrap()
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum External {
+        Unit,
+        Newtype(u8),
+        Tuple(u8, char),
+        Struct { a: u8, b: Option<String> },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Inner {
+        a: u8,
+        b: External,
+    }
+
+    fn external_variants() -> Vec<External> {
+        vec![
+            External::Unit,
+            External::Newtype(1),
+            External::Tuple(1, 'a'),
+            External::Struct {
+                a: 1,
+                b: Some("Hello World!".to_string()),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_untagged_enum_deserialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        #[serde(untagged)]
+        enum Untagged {
+            Unit,
+            Bytes(#[serde(with = "serde_bytes_like")] Vec<u8>),
+            Newtype(String),
+            Tuple(u8, char),
+            Struct { a: u8, b: External },
+            Nested(External),
+        }
+
+        deserialization_test(Untagged::Unit);
+        deserialization_test(Untagged::Newtype("Hello World!".to_string()));
+        deserialization_test(Untagged::Tuple(1, 'a'));
+        deserialization_test(Untagged::Bytes(b"Hello World!".to_vec()));
+        for external in external_variants() {
+            v2_deserialization_test(Untagged::Struct { a: 1, b: external });
+        }
+        for external in external_variants() {
+            v2_deserialization_test(Untagged::Nested(external));
+        }
+    }
+
+    #[test]
+    fn test_internally_tagged_enum_deserialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        #[serde(tag = "type")]
+        enum Internal {
+            Unit,
+            Newtype(Inner),
+            Struct {
+                a: Option<u8>,
+                b: External,
+            },
+            #[serde(rename = "renamed")]
+            Renamed {
+                c: (u8, String),
+            },
+        }
+
+        deserialization_test(Internal::Unit);
+        deserialization_test(Internal::Renamed {
+            c: (1, "Hello World!".to_string()),
+        });
+        for external in external_variants() {
+            v2_deserialization_test(Internal::Newtype(Inner { a: 1, b: external }));
+        }
+        for external in external_variants() {
+            v2_deserialization_test(Internal::Struct {
+                a: Some(1),
+                b: external,
+            });
+        }
+    }
+
+    #[test]
+    fn test_adjacently_tagged_enum_deserialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        #[serde(tag = "t", content = "c")]
+        enum Adjacent {
+            Unit,
+            Newtype(External),
+            Tuple(u8, char),
+            Struct { a: u8, b: External },
+        }
+
+        deserialization_test(Adjacent::Unit);
+        deserialization_test(Adjacent::Tuple(1, 'a'));
+        for external in external_variants() {
+            v2_deserialization_test(Adjacent::Newtype(external));
+        }
+        for external in external_variants() {
+            v2_deserialization_test(Adjacent::Struct { a: 1, b: external });
+        }
+    }
+
+    #[test]
+    fn test_untagged_variant_deserialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Mixed {
+            Tagged(u8),
+            #[serde(untagged)]
+            Fallback(External),
+        }
+
+        deserialization_test(Mixed::Tagged(1));
+        for external in external_variants() {
+            v2_deserialization_test(Mixed::Fallback(external));
+        }
+    }
+
+    #[test]
+    fn test_ignored_enum_deserialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Full {
+            a: u8,
+            b: External,
+            c: u8,
+        }
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Partial {
+            a: u8,
+            c: u8,
+        }
+
+        let v2 = SbifOptions::new()
+            .compression(Compression::None)
+            .version(FormatVersion::V2);
+        for external in external_variants() {
+            let full = Full {
+                a: 1,
+                b: external,
+                c: 2,
+            };
+            let serialized = crate::to_bytes_with(&full, &v2).unwrap();
+            let deserialized: Partial = crate::de::from_slice(&serialized).unwrap();
+            assert_eq!(deserialized, Partial { a: 1, c: 2 });
+        }
+
+        // Skipping the fields of a version 1 tuple variant needs the type of the variant.
+        let full = Full {
+            a: 1,
+            b: External::Tuple(1, 'a'),
+            c: 2,
+        };
+        let serialized = to_bytes(&full, Compression::None).unwrap();
+        assert!(matches!(
+            crate::de::from_slice::<Partial>(&serialized).map_err(Error::into_inner),
+            Err(Error::UntypedVariant(2))
+        ));
+    }
+
+    mod serde_bytes_like {
+        pub fn serialize<S: serde::Serializer>(
+            bytes: &[u8],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(bytes)
+        }
+
+        pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<u8>, D::Error> {
+            struct BytesVisitor;
+
+            impl<'de> serde::de::Visitor<'de> for BytesVisitor {
+                type Value = Vec<u8>;
+
+                fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    formatter.write_str("bytes")
+                }
+
+                fn visit_bytes<E>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+                    Ok(v.to_vec())
+                }
+            }
+
+            deserializer.deserialize_bytes(BytesVisitor)
+        }
+    }
+
     #[test]
     fn test_option_deserialization() {
         deserialization_test(None::<u8>);
         deserialization_test(Some(1_u8));
     }
+
+    fn limited<T: Serialize + DeserializeOwned>(
+        value: &T,
+        compression: Compression,
+        options: &DeserializerOptions,
+    ) -> Result<T, Error> {
+        let serialized = to_bytes(value, compression).unwrap();
+        let mut deserializer = Deserializer::from_slice_with_options(&serialized, options.clone())?;
+        T::deserialize(&mut deserializer)
+    }
+
+    fn assert_limit<T: Debug>(result: Result<T, Error>, expected: Limit) {
+        match result.map_err(Error::into_inner) {
+            Err(Error::LimitExceeded { limit, .. }) => assert_eq!(limit, expected),
+            other => panic!("expected {} limit error, got {:?}", expected, other),
+        }
+    }
+
+    #[test]
+    fn test_allocation_limit() {
+        let options = DeserializerOptions {
+            max_allocation: 4,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            limited(&"abcd".to_string(), Compression::None, &options).unwrap(),
+            "abcd"
+        );
+        assert_limit(
+            limited(&"Hello World!".to_string(), Compression::None, &options),
+            Limit::Allocation,
+        );
+        assert_limit(
+            limited(
+                &Value::Bytes(b"Hello World!".to_vec()),
+                Compression::GZip(6),
+                &options,
+            ),
+            Limit::Allocation,
+        );
+    }
+
+    #[test]
+    fn test_total_bytes_limit() {
+        let options = DeserializerOptions {
+            max_total_bytes: 64,
+            ..Default::default()
+        };
+
+        let value = vec![0_u64; 32];
+        assert_limit(
+            limited(&value, Compression::GZip(6), &options),
+            Limit::TotalBytes,
+        );
+        assert_limit(
+            limited(&vec!["a".repeat(60)], Compression::None, &options),
+            Limit::TotalBytes,
+        );
+    }
+
+    #[test]
+    fn test_collection_length_limit() {
+        let options = DeserializerOptions {
+            max_collection_length: 4,
+            ..Default::default()
+        };
+
+        assert!(limited(&vec![1_u8; 4], Compression::None, &options).is_ok());
+        assert_limit(
+            limited(&vec![1_u8; 5], Compression::None, &options),
+            Limit::CollectionLength,
+        );
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let options = DeserializerOptions {
+            max_depth: 2,
+            ..Default::default()
+        };
+
+        assert!(limited(&vec![vec![1_u8]], Compression::None, &options).is_ok());
+        assert_limit(
+            limited(&vec![vec![vec![1_u8]]], Compression::None, &options),
+            Limit::Depth,
+        );
+
+        let mut nested = v2_header();
+        for _ in 0..100_000 {
+            nested.extend([data_ids::SEQ_ID, 0, 0, 0, 1]);
+        }
+        nested.push(data_ids::NULL_ID);
+        assert_limit(crate::de::from_slice::<Value>(&nested), Limit::Depth);
+    }
+
+    #[test]
+    fn test_oversized_length_does_not_allocate() {
+        let mut serialized = v2_header();
+        serialized.extend([data_ids::STR_ID, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+        for result in [
+            crate::de::from_slice::<String>(&serialized),
+            crate::de::from_reader::<_, String>(serialized.as_slice()),
+        ] {
+            match result.map_err(Error::into_inner) {
+                Err(Error::IoError(e)) => assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof),
+                other => panic!("expected an unexpected eof error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_error_position() {
+        #[derive(Serialize)]
+        struct Written {
+            players: Vec<HashMap<String, Value>>,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Inventory {
+            #[allow(dead_code)]
+            slot: u8,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Player {
+            #[allow(dead_code)]
+            inventory: Inventory,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Read {
+            #[allow(dead_code)]
+            players: Vec<Player>,
+        }
+
+        let player = |slot: Value| {
+            HashMap::from([(
+                "inventory".to_string(),
+                Value::Map(vec![(Value::Str("slot".to_string()), slot)]),
+            )])
+        };
+        let serialized = to_bytes(
+            &Written {
+                players: vec![player(Value::U8(1)), player(Value::Str("a".to_string()))],
+            },
+            Compression::GZip(6),
+        )
+        .unwrap();
+
+        let error = crate::de::from_slice::<Read>(&serialized).unwrap_err();
+        assert_eq!(error.path(), Some(".players[1].inventory.slot"));
+        assert!(error.offset().is_some());
+        assert!(matches!(
+            error.kind(),
+            Error::InvalidDataId {
+                found: data_ids::STR_ID,
+                ..
+            }
+        ));
+
+        let error = crate::de::from_slice::<External>(&serialized).unwrap_err();
+        assert_eq!(error.path(), Some("."));
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut serialized = to_bytes(&1_u8, Compression::None).unwrap();
+        serialized.extend(b"extra");
+
+        assert!(matches!(
+            crate::de::from_slice::<u8>(&serialized).map_err(Error::into_inner),
+            Err(Error::TrailingData)
+        ));
+        assert!(matches!(
+            crate::de::from_reader::<_, u8>(serialized.as_slice()).map_err(Error::into_inner),
+            Err(Error::TrailingData)
+        ));
+
+        let mut deserializer = Deserializer::new(serialized.as_slice()).unwrap();
+        assert_eq!(u8::deserialize(&mut deserializer).unwrap(), 1);
+        let mut rest = Vec::new();
+        std::io::Read::read_to_end(&mut deserializer.into_inner(), &mut rest).unwrap();
+        assert_eq!(rest, b"extra");
+    }
+
+    #[test]
+    fn test_data_after_document() {
+        for compression in compressions() {
+            let mut serialized = to_bytes(&"document", compression).unwrap();
+            serialized.extend(b"trailer");
+
+            // `end` reads a compressed body up to the end of its stream and keeps the byte it peeks at.
+            let mut deserializer = Deserializer::new(serialized.as_slice()).unwrap();
+            assert_eq!(String::deserialize(&mut deserializer).unwrap(), "document");
+            assert!(matches!(deserializer.end(), Err(Error::TrailingData)));
+            let mut rest = Vec::new();
+            std::io::Read::read_to_end(&mut deserializer.into_inner().into_inner(), &mut rest)
+                .unwrap();
+            assert_eq!(rest, b"trailer", "{:?}", compression);
+
+            let mut deserializer = Deserializer::from_slice(&serialized).unwrap();
+            assert_eq!(String::deserialize(&mut deserializer).unwrap(), "document");
+            assert!(matches!(deserializer.end(), Err(Error::TrailingData)));
+            assert_eq!(deserializer.into_inner().remaining(), b"trailer");
+        }
+    }
+
+    #[test]
+    fn test_data_after_compressed_body() {
+        for compression in compressions() {
+            let mut concatenated = to_bytes(&"first", compression).unwrap();
+            concatenated.extend(to_bytes(&"second", compression).unwrap());
+            let mut garbage = to_bytes(&"first", compression).unwrap();
+            garbage.extend(b"garbage");
+
+            for serialized in [concatenated, garbage] {
+                assert!(matches!(
+                    crate::de::from_slice::<String>(&serialized).map_err(Error::into_inner),
+                    Err(Error::TrailingData)
+                ));
+                assert!(matches!(
+                    crate::de::from_reader::<_, String>(serialized.as_slice())
+                        .map_err(Error::into_inner),
+                    Err(Error::TrailingData)
+                ));
+            }
+        }
+    }
+
+    fn compressions() -> Vec<Compression> {
+        vec![
+            Compression::None,
+            Compression::Deflate(6),
+            Compression::GZip(6),
+            Compression::ZLib(6),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(3),
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+        ]
+    }
+
+    #[test]
+    fn test_stream_bodies() {
+        let values = external_variants();
+        for compression in compressions() {
+            let mut serializer = crate::Serializer::new(Vec::new(), compression).unwrap();
+            for value in &values {
+                value.serialize(&mut serializer).unwrap();
+            }
+            let serialized = serializer.finish().unwrap();
+
+            let deserializer = Deserializer::new(serialized.as_slice()).unwrap();
+            let deserialized = deserializer
+                .into_stream::<External>()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(deserialized, values);
+        }
+    }
+
+    #[test]
+    fn test_stream_documents() {
+        let mut serialized = Vec::new();
+        let mut expected = Vec::new();
+        for (i, compression) in compressions().into_iter().enumerate() {
+            let value = format!("Document {}", i);
+            match i % 2 {
+                0 => crate::to_writer(&mut serialized, &value, compression).unwrap(),
+                _ => {
+                    let options = SbifOptions::new()
+                        .compression(compression)
+                        .byte_order::<LittleEndian>();
+                    serialized.extend(crate::to_bytes_with(&value, &options).unwrap());
+                }
+            }
+            expected.push(value);
+        }
+
+        let deserialized = StreamDeserializer::<_, String>::documents(serialized.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(deserialized, expected);
+
+        assert_eq!(
+            StreamDeserializer::<_, String>::documents(&[][..]).count(),
+            0
+        );
+
+        crate::to_writer(&mut serialized, &"Truncated", Compression::None).unwrap();
+        let truncated = &serialized[..serialized.len() - 1];
+        let mut stream = StreamDeserializer::<_, String>::documents(truncated);
+        assert!(stream.by_ref().take(expected.len()).all(|v| v.is_ok()));
+        assert!(stream.next().unwrap().is_err());
+        assert!(stream.next().is_none());
+    }
+
+    fn checksums() -> Vec<Checksum> {
+        vec![
+            #[cfg(feature = "crc32")]
+            Checksum::Crc32,
+            #[cfg(feature = "xxh3")]
+            Checksum::Xxh3,
+        ]
+    }
+
+    #[test]
+    fn test_checksum() {
+        for checksum in checksums() {
+            let options = SerializerOptions {
+                checksum: Some(checksum),
+                ..SerializerOptions::default()
+            };
+            for compression in compressions() {
+                let mut serializer =
+                    Serializer::with_options(Vec::new(), compression, options.clone()).unwrap();
+                "Hello World!".serialize(&mut serializer).unwrap();
+                let serialized = serializer.finish().unwrap();
+                assert_eq!(
+                    crate::read_header(serialized.as_slice())
+                        .unwrap()
+                        .checksum(),
+                    Some(checksum)
+                );
+                assert_eq!(
+                    crate::de::from_slice::<String>(&serialized).unwrap(),
+                    "Hello World!"
+                );
+                assert_eq!(
+                    crate::de::from_reader::<_, String>(serialized.as_slice()).unwrap(),
+                    "Hello World!"
+                );
+
+                let documents = [serialized.as_slice(), serialized.as_slice()].concat();
+                let deserialized = StreamDeserializer::<_, String>::documents(documents.as_slice())
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                assert_eq!(deserialized, ["Hello World!", "Hello World!"]);
+
+                let mut serializer =
+                    Serializer::with_options(Vec::new(), compression, options.clone()).unwrap();
+                for value in 0..3_u16 {
+                    value.serialize(&mut serializer).unwrap();
+                }
+                let serialized = serializer.finish().unwrap();
+                let deserialized = Deserializer::new(serialized.as_slice())
+                    .unwrap()
+                    .into_stream::<u16>()
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                assert_eq!(deserialized, [0, 1, 2]);
+
+                // Dropping the serializer without finishing it leaves out the checksum.
+                let mut dropped = Vec::new();
+                let mut serializer =
+                    Serializer::with_options(&mut dropped, compression, options.clone()).unwrap();
+                "Hello World!".serialize(&mut serializer).unwrap();
+                drop(serializer);
+                assert!(crate::de::from_slice::<String>(&dropped).is_err());
+            }
+
+            let serialized = crate::to_bytes_with(
+                &"Hello World!",
+                &SbifOptions::new()
+                    .compression(Compression::None)
+                    .checksum(checksum),
+            )
+            .unwrap();
+            let mut corrupted = serialized.clone();
+            corrupted[serialized.len() - 10] = b'w';
+            assert!(matches!(
+                crate::de::from_slice::<String>(&corrupted).map_err(Error::into_inner),
+                Err(Error::ChecksumMismatch { .. })
+            ));
+
+            let truncated = &serialized[..serialized.len() - 1];
+            assert!(crate::de::from_slice::<String>(truncated).is_err());
+            let truncated = &serialized[..serialized.len() - checksum_len(checksum) - 1];
+            assert!(crate::de::from_slice::<String>(truncated).is_err());
+        }
+
+        let mut header = v2_header();
+        header[6] = 4;
+        header[8] = 0x18;
+        assert!(matches!(
+            crate::read_header(header.as_slice()),
+            Err(Error::UnsupportedFlags(0x18))
+        ));
+    }
+
+    fn checksum_len(checksum: Checksum) -> usize {
+        match checksum {
+            #[cfg(feature = "crc32")]
+            Checksum::Crc32 => 4,
+            #[cfg(feature = "xxh3")]
+            Checksum::Xxh3 => 8,
+        }
+    }
 }
@@ -0,0 +1,310 @@
+use std::{
+    io::{self, Read, Write},
+    marker::PhantomData,
+};
+
+use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt};
+
+/// The byte order of fixed-size numbers in the body of a file, as recorded in its header. Serializers and
+/// deserializers take the byte order as a type parameter, [`crate::BigEndian`] or [`crate::LittleEndian`],
+/// which [`crate::from_slice`] and [`crate::from_reader`] choose from the header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Endianness {
+    /// The most significant byte comes first. This is the default, and the only byte order of version 1 files.
+    #[default]
+    Big,
+    /// The least significant byte comes first, which avoids byte swaps on most processors.
+    Little,
+}
+
+impl Endianness {
+    /// The byte order of `B`, which is either big or little endian.
+    pub(crate) fn of<B: ByteOrder>() -> Self {
+        match B::read_u16(&[0, 1]) {
+            1 => Self::Big,
+            _ => Self::Little,
+        }
+    }
+}
+
+/// How lengths and numbers are written in the body of a file. Numbers that are not written as varints use
+/// the byte order `B`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Encoding<B> {
+    /// Lengths and unsigned integers are written as LEB128 varints and signed integers as zigzag varints.
+    pub(crate) varint: bool,
+    order: PhantomData<B>,
+}
+
+impl<B: ByteOrder> Encoding<B> {
+    pub(crate) fn new(varint: bool) -> Self {
+        Self {
+            varint,
+            order: PhantomData,
+        }
+    }
+
+    /// Writes the length of a string, byte array or container.
+    pub(crate) fn write_length<W: Write>(self, writer: &mut W, length: usize) -> io::Result<()> {
+        self.write_u32(writer, length as u32)
+    }
+
+    pub(crate) fn write_u16<W: Write>(self, writer: &mut W, v: u16) -> io::Result<()> {
+        match self.varint {
+            true => write_varint(writer, v as u128),
+            false => writer.write_u16::<B>(v),
+        }
+    }
+
+    pub(crate) fn write_u32<W: Write>(self, writer: &mut W, v: u32) -> io::Result<()> {
+        match self.varint {
+            true => write_varint(writer, v as u128),
+            false => writer.write_u32::<B>(v),
+        }
+    }
+
+    pub(crate) fn write_u64<W: Write>(self, writer: &mut W, v: u64) -> io::Result<()> {
+        match self.varint {
+            true => write_varint(writer, v as u128),
+            false => writer.write_u64::<B>(v),
+        }
+    }
+
+    pub(crate) fn write_u128<W: Write>(self, writer: &mut W, v: u128) -> io::Result<()> {
+        match self.varint {
+            true => write_varint(writer, v),
+            false => writer.write_u128::<B>(v),
+        }
+    }
+
+    pub(crate) fn write_i16<W: Write>(self, writer: &mut W, v: i16) -> io::Result<()> {
+        match self.varint {
+            true => write_varint(writer, zigzag(v as i128)),
+            false => writer.write_i16::<B>(v),
+        }
+    }
+
+    pub(crate) fn write_i32<W: Write>(self, writer: &mut W, v: i32) -> io::Result<()> {
+        match self.varint {
+            true => write_varint(writer, zigzag(v as i128)),
+            false => writer.write_i32::<B>(v),
+        }
+    }
+
+    pub(crate) fn write_i64<W: Write>(self, writer: &mut W, v: i64) -> io::Result<()> {
+        match self.varint {
+            true => write_varint(writer, zigzag(v as i128)),
+            false => writer.write_i64::<B>(v),
+        }
+    }
+
+    pub(crate) fn write_i128<W: Write>(self, writer: &mut W, v: i128) -> io::Result<()> {
+        match self.varint {
+            true => write_varint(writer, zigzag(v)),
+            false => writer.write_i128::<B>(v),
+        }
+    }
+
+    pub(crate) fn write_f32<W: Write>(self, writer: &mut W, v: f32) -> io::Result<()> {
+        writer.write_f32::<B>(v)
+    }
+
+    pub(crate) fn write_f64<W: Write>(self, writer: &mut W, v: f64) -> io::Result<()> {
+        writer.write_f64::<B>(v)
+    }
+
+    /// Reads the length of a string, byte array or container.
+    pub(crate) fn read_length<R: Read>(self, reader: &mut R) -> io::Result<usize> {
+        self.read_u32(reader).map(|length| length as usize)
+    }
+
+    pub(crate) fn read_u16<R: Read>(self, reader: &mut R) -> io::Result<u16> {
+        match self.varint {
+            true => read_varint(reader, 16).map(|v| v as u16),
+            false => reader.read_u16::<B>(),
+        }
+    }
+
+    pub(crate) fn read_u32<R: Read>(self, reader: &mut R) -> io::Result<u32> {
+        match self.varint {
+            true => read_varint(reader, 32).map(|v| v as u32),
+            false => reader.read_u32::<B>(),
+        }
+    }
+
+    pub(crate) fn read_u64<R: Read>(self, reader: &mut R) -> io::Result<u64> {
+        match self.varint {
+            true => read_varint(reader, 64).map(|v| v as u64),
+            false => reader.read_u64::<B>(),
+        }
+    }
+
+    pub(crate) fn read_u128<R: Read>(self, reader: &mut R) -> io::Result<u128> {
+        match self.varint {
+            true => read_varint(reader, 128),
+            false => reader.read_u128::<B>(),
+        }
+    }
+
+    pub(crate) fn read_i16<R: Read>(self, reader: &mut R) -> io::Result<i16> {
+        match self.varint {
+            true => read_varint(reader, 16).map(|v| unzigzag(v) as i16),
+            false => reader.read_i16::<B>(),
+        }
+    }
+
+    pub(crate) fn read_i32<R: Read>(self, reader: &mut R) -> io::Result<i32> {
+        match self.varint {
+            true => read_varint(reader, 32).map(|v| unzigzag(v) as i32),
+            false => reader.read_i32::<B>(),
+        }
+    }
+
+    pub(crate) fn read_i64<R: Read>(self, reader: &mut R) -> io::Result<i64> {
+        match self.varint {
+            true => read_varint(reader, 64).map(|v| unzigzag(v) as i64),
+            false => reader.read_i64::<B>(),
+        }
+    }
+
+    pub(crate) fn read_i128<R: Read>(self, reader: &mut R) -> io::Result<i128> {
+        match self.varint {
+            true => read_varint(reader, 128).map(unzigzag),
+            false => reader.read_i128::<B>(),
+        }
+    }
+
+    pub(crate) fn read_f32<R: Read>(self, reader: &mut R) -> io::Result<f32> {
+        reader.read_f32::<B>()
+    }
+
+    pub(crate) fn read_f64<R: Read>(self, reader: &mut R) -> io::Result<f64> {
+        reader.read_f64::<B>()
+    }
+
+    /// The number of bytes at the start of `bytes` taken by a value with the given numeric id.
+    pub(crate) fn value_len(self, id: u8, bytes: &[u8]) -> usize {
+        match (self.varint, id) {
+            (true, crate::data_ids::I8_ID | crate::data_ids::U8_ID)
+            | (true, crate::data_ids::F32_ID | crate::data_ids::F64_ID)
+            | (false, _) => crate::data_ids::packed_width(id).unwrap_or(0),
+            (true, _) => bytes.iter().take_while(|&&b| b & 0x80 != 0).count() + 1,
+        }
+    }
+}
+
+/// Writes an unsigned LEB128 varint, 7 bits per byte starting with the least significant bits.
+fn write_varint<W: Write>(writer: &mut W, mut v: u128) -> io::Result<()> {
+    let mut buffer = [0_u8; 19];
+    let mut length = 0;
+    loop {
+        let byte = (v & 0x7F) as u8;
+        v >>= 7;
+        if v == 0 {
+            buffer[length] = byte;
+            length += 1;
+            break;
+        }
+
+        buffer[length] = byte | 0x80;
+        length += 1;
+    }
+
+    writer.write_all(&buffer[..length])
+}
+
+/// Reads an unsigned LEB128 varint, failing if its value does not fit in `bits` bits.
+fn read_varint<R: Read>(reader: &mut R, bits: u32) -> io::Result<u128> {
+    let mut value = 0_u128;
+    let mut shift = 0;
+    loop {
+        let byte = reader.read_u8()?;
+        let low = (byte & 0x7F) as u128;
+        if shift >= bits || (bits - shift < 7 && low >> (bits - shift) != 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("varint does not fit in {} bits", bits),
+            ));
+        }
+
+        value |= low << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+
+        shift += 7;
+    }
+}
+
+/// Maps signed integers to unsigned ones so that values close to zero have short varints.
+fn zigzag(v: i128) -> u128 {
+    ((v << 1) ^ (v >> 127)) as u128
+}
+
+fn unzigzag(v: u128) -> i128 {
+    ((v >> 1) as i128) ^ -((v & 1) as i128)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint_round_trip() {
+        let encoding = Encoding::<byteorder::BigEndian>::new(true);
+        for v in [0, 1, 127, 128, 300, u32::MAX] {
+            let mut buffer = Vec::new();
+            encoding.write_u32(&mut buffer, v).unwrap();
+            assert_eq!(encoding.read_u32(&mut buffer.as_slice()).unwrap(), v);
+        }
+
+        for v in [0, -1, 1, -64, 64, i64::MIN, i64::MAX] {
+            let mut buffer = Vec::new();
+            encoding.write_i64(&mut buffer, v).unwrap();
+            assert_eq!(encoding.read_i64(&mut buffer.as_slice()).unwrap(), v);
+        }
+
+        for v in [i128::MIN, i128::MAX] {
+            let mut buffer = Vec::new();
+            encoding.write_i128(&mut buffer, v).unwrap();
+            assert_eq!(encoding.read_i128(&mut buffer.as_slice()).unwrap(), v);
+        }
+
+        let mut buffer = Vec::new();
+        encoding.write_u16(&mut buffer, 300).unwrap();
+        assert_eq!(buffer, [0xAC, 0x02]);
+        let mut buffer = Vec::new();
+        encoding.write_i32(&mut buffer, -3).unwrap();
+        assert_eq!(buffer, [5]);
+    }
+
+    #[test]
+    fn test_varint_overflow() {
+        let encoding = Encoding::<byteorder::BigEndian>::new(true);
+        let mut buffer = Vec::new();
+        encoding.write_u32(&mut buffer, 70_000).unwrap();
+        assert!(encoding.read_u16(&mut buffer.as_slice()).is_err());
+        assert!(encoding.read_u64(&mut [0xFF_u8; 11].as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_byte_order() {
+        assert_eq!(Endianness::of::<byteorder::BigEndian>(), Endianness::Big);
+        assert_eq!(
+            Endianness::of::<byteorder::LittleEndian>(),
+            Endianness::Little
+        );
+
+        let mut buffer = Vec::new();
+        Encoding::<byteorder::LittleEndian>::new(false)
+            .write_u32(&mut buffer, 1)
+            .unwrap();
+        assert_eq!(buffer, [1, 0, 0, 0]);
+        assert_eq!(
+            Encoding::<byteorder::BigEndian>::new(false)
+                .read_u32(&mut buffer.as_slice())
+                .unwrap(),
+            1 << 24
+        );
+    }
+}
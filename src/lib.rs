@@ -1,15 +1,39 @@
+// err-derive expands its impls inside an anonymous const.
+#![allow(non_local_definitions)]
+
 use std::io::{Read, Write};
 
 use byteorder::{ReadBytesExt, WriteBytesExt};
 use err_derive::Error;
 
+use crate::encoding::Encoding;
+
+/// The byte order of the file header, which is always big endian.
 pub(crate) type ByteOrder = byteorder::BigEndian;
 
+mod checksum;
 mod de;
+mod encoding;
+mod metadata;
+mod options;
+mod read;
 mod se;
+mod value;
 
-pub use crate::de::{from_reader, from_slice, Deserializer};
-pub use crate::se::{to_bytes, to_writer, Serializer};
+pub use crate::checksum::Checksum;
+pub use crate::de::{
+    from_reader, from_slice, from_slice_with, read_header, read_header_with, Deserializer,
+    DeserializerOptions, StreamDeserializer,
+};
+pub use crate::encoding::Endianness;
+pub use crate::metadata::Metadata;
+pub use crate::options::SbifOptions;
+pub use crate::read::{IoRead, SliceRead};
+pub use crate::se::{
+    to_bytes, to_bytes_with, to_writer, EnumEncoding, Serializer, SerializerOptions, StructEncoding,
+};
+pub use crate::value::{from_value, to_value, Value};
+pub use byteorder::{BigEndian, LittleEndian};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -35,16 +59,59 @@ pub(crate) mod data_ids {
     pub const ENUM_VARIANT_ID: u8 = 18;
     pub const TUPLE_STRUCT_ID: u8 = 19;
     pub const MAP_ID: u8 = 20;
+    pub const I128_ID: u8 = 21;
+    pub const U128_ID: u8 = 22;
+    pub const PACKED_SEQ_ID: u8 = 23;
+    pub const INTERNED_STR_ID: u8 = 24;
+    pub const STR_REF_ID: u8 = 25;
+    pub const NAMED_UNIT_VARIANT_ID: u8 = 26;
+    pub const NAMED_ENUM_VARIANT_ID: u8 = 27;
+
+    /// The highest data id, used when reporting an invalid id.
+    pub const LAST_ID: u8 = NAMED_ENUM_VARIANT_ID;
+
+    /// Marks the checksum at the end of the body of a file with a checksum. It never starts a value.
+    pub const CHECKSUM_ID: u8 = 28;
+
+    /// The size of a value with the given id inside a packed sequence, or `None` if the id cannot be packed.
+    pub fn packed_width(id: u8) -> Option<usize> {
+        match id {
+            I8_ID | U8_ID => Some(1),
+            I16_ID | U16_ID => Some(2),
+            I32_ID | U32_ID | F32_ID => Some(4),
+            I64_ID | U64_ID | F64_ID => Some(8),
+            I128_ID | U128_ID => Some(16),
+            _ => None,
+        }
+    }
 }
 
+/// Errors returned while serializing or deserializing.
+///
+/// Errors returned while deserializing the body of a file are wrapped in [`Error::Positioned`], which
+/// records where the error occurred. Match on [`Error::kind`] to handle the underlying error.
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(display = "IO error: {}", _0)]
     IoError(#[source] std::io::Error),
     #[error(display = "From utf8 error: {}", _0)]
     FromUtf8Error(#[source] std::string::FromUtf8Error),
+    #[error(display = "Utf8 error: {}", _0)]
+    Utf8Error(#[source] std::str::Utf8Error),
     #[error(display = "'{}' is not a valid compression format", _0)]
     InvalidCompression(u8),
+    #[error(
+        display = "{} compression is not supported, enable the '{}' feature to use it",
+        _0,
+        _0
+    )]
+    UnsupportedCompression(&'static str),
+    #[error(
+        display = "{} checksums are not supported, enable the '{}' feature to use them",
+        _0,
+        _0
+    )]
+    UnsupportedChecksum(&'static str),
     #[error(display = "{}", _0)]
     Custom(String),
     #[error(display = "Lengths are required for the sbif format")]
@@ -55,22 +122,140 @@ pub enum Error {
         display = "Invalid access order. You cannot access 2 map keys or 2 map values in a row"
     )]
     InvalidMapAccess,
-    #[error(display = "Invalid sbif header: expected 'SBIF', found {}", _0)]
-    InvalidHeader(String),
+    #[error(
+        display = "Invalid sbif header: expected {}, found '{}'",
+        expected,
+        found
+    )]
+    InvalidHeader { expected: String, found: String },
     #[error(display = "Invalid data id: expected {}, found {}", expected, found)]
     InvalidDataId { expected: String, found: u8 },
+    /// A non-unit variant of a version 1 file whose payload may be the fields of a tuple or struct variant,
+    /// which have no data id and can only be read by a deserializer that knows the type of the variant.
+    #[error(
+        display = "Self-describing read of version 1 variant {} is unsupported, as it may be a tuple or struct variant",
+        _0
+    )]
+    UntypedVariant(u32),
     #[error(
         display = "Invalid sbif version: expected {}, found {}",
         expected,
         found
     )]
     InvalidVersion { expected: u8, found: u8 },
+    #[error(display = "Unsupported sbif header flags: {:#04x}", _0)]
+    UnsupportedFlags(u8),
+    #[error(
+        display = "Byte order mismatch: expected a {:?} endian body, found {:?} endian",
+        expected,
+        found
+    )]
+    ByteOrderMismatch {
+        expected: Endianness,
+        found: Endianness,
+    },
     #[error(display = "{}: expected {}, actual {}", message, expected, actual)]
     InvalidLength {
         expected: usize,
         actual: usize,
         message: String,
     },
+    #[error(display = "{} limit of {} exceeded", limit, maximum)]
+    LimitExceeded { limit: Limit, maximum: usize },
+    #[error(
+        display = "Invalid string reference: {} strings have been interned, found index {}",
+        interned,
+        index
+    )]
+    InvalidStringReference { index: u32, interned: usize },
+    #[error(
+        display = "Field '{}' cannot be skipped when structs are encoded positionally",
+        _0
+    )]
+    SkippedField(&'static str),
+    #[error(display = "Unexpected data after the end of the root value")]
+    TrailingData,
+    #[error(
+        display = "Checksum mismatch: expected {:#x}, computed {:#x}",
+        expected,
+        actual
+    )]
+    ChecksumMismatch { expected: u64, actual: u64 },
+    /// An error that occurred while deserializing the body, with the offset into the decompressed data
+    /// and the path to the value that failed. The wrapped error is never itself positioned.
+    #[error(display = "{} at {} (byte offset {})", error, path, offset)]
+    Positioned {
+        offset: usize,
+        path: String,
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    /// The offset into the decompressed data at which deserialization failed, if known.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Self::Positioned { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
+
+    /// The path to the value that failed to deserialize, such as `.players[42].inventory.slot`, if known.
+    /// Struct fields are named by field, and map entries by their position in the map.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            Self::Positioned { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The underlying error, without the offset and path. This is the error itself if it is not positioned.
+    ///
+    /// Example
+    /// ```
+    /// use serde_sbif::{Compression, Error};
+    /// let bytes = serde_sbif::to_bytes(&"text", Compression::None).unwrap();
+    /// let error = serde_sbif::from_slice::<u32>(&bytes).unwrap_err();
+    /// assert!(matches!(error.kind(), Error::InvalidDataId { .. }));
+    /// ```
+    pub fn kind(&self) -> &Error {
+        match self {
+            Self::Positioned { error, .. } => error,
+            error => error,
+        }
+    }
+
+    /// Converts the error into the underlying error, discarding the offset and path.
+    pub fn into_inner(self) -> Error {
+        match self {
+            Self::Positioned { error, .. } => *error,
+            error => error,
+        }
+    }
+}
+
+/// A resource limit enforced by [`DeserializerOptions`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// The length of a single string or byte array.
+    Allocation,
+    /// The number of bytes decoded from the input.
+    TotalBytes,
+    /// The number of elements in a sequence, tuple or map.
+    CollectionLength,
+    /// The depth of nested values.
+    Depth,
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Allocation => write!(f, "Allocation"),
+            Self::TotalBytes => write!(f, "Total bytes"),
+            Self::CollectionLength => write!(f, "Collection length"),
+            Self::Depth => write!(f, "Nesting depth"),
+        }
+    }
 }
 
 impl serde::ser::Error for Error {
@@ -91,13 +276,21 @@ impl serde::de::Error for Error {
     }
 }
 
-/// The compression format and level used for the sbif file
+/// The compression format and level used for the sbif file. Formats behind a feature flag are only
+/// available when it is enabled, so matches must handle formats they do not know about.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Compression {
     None,
     Deflate(u32),
     GZip(u32),
     ZLib(u32),
+    /// Zstandard compression with the given level. Requires the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+    /// LZ4 frame compression. Requires the `lz4` feature.
+    #[cfg(feature = "lz4")]
+    Lz4,
 }
 
 impl Default for Compression {
@@ -106,10 +299,115 @@ impl Default for Compression {
     }
 }
 
+/// A version of the SBIF format, which covers the layout of the header and of the body. Readers accept
+/// every version up to [`FormatVersion::LATEST`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FormatVersion {
+    /// The name and compression format only. The body uses fixed-size big endian numbers and the data ids
+    /// up to 20, and the fields of tuple and struct variants follow the variant index directly.
+    V1 = 1,
+    /// Adds a byte of flags for the encoding of the body, and the data ids from 21 onwards for 128-bit
+    /// integers, packed sequences, interned strings and named variants. The fields of tuple and struct
+    /// variants are wrapped in a tuple or map, so that variants can be read without knowing their type.
+    V2 = 2,
+    /// Adds an optional metadata block after the flags.
+    V3 = 3,
+    /// Adds flags for a checksum at the end of the body.
+    V4 = 4,
+}
+
+impl FormatVersion {
+    /// The newest version that can be read and written.
+    pub const LATEST: Self = Self::V4;
+
+    /// The flags that may be set in a header of this version.
+    fn known_flags(self) -> u8 {
+        match self {
+            Self::V1 => 0,
+            Self::V2 => FileHeader::VARINT_FLAG | FileHeader::LITTLE_ENDIAN_FLAG,
+            Self::V3 => Self::V2.known_flags() | FileHeader::METADATA_FLAG,
+            Self::V4 => Self::V3.known_flags() | FileHeader::CRC32_FLAG | FileHeader::XXH3_FLAG,
+        }
+    }
+}
+
+impl From<FormatVersion> for u8 {
+    fn from(version: FormatVersion) -> Self {
+        version as u8
+    }
+}
+
+impl TryFrom<u8> for FormatVersion {
+    type Error = Error;
+
+    fn try_from(version: u8) -> Result<Self> {
+        match version {
+            1 => Ok(Self::V1),
+            2 => Ok(Self::V2),
+            3 => Ok(Self::V3),
+            4 => Ok(Self::V4),
+            found => Err(Error::InvalidVersion {
+                expected: Self::LATEST.into(),
+                found,
+            }),
+        }
+    }
+}
+
+/// The header at the start of an SBIF file, which is never compressed and can be read with [`read_header`]
+/// without decoding the rest of the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header(pub(crate) FileHeader);
+
+impl Header {
+    /// The magic name at the start of the file.
+    pub fn name(&self) -> &str {
+        &self.0.header_name
+    }
+
+    /// The version of the format the file was written with.
+    pub fn version(&self) -> FormatVersion {
+        self.0.version
+    }
+
+    /// The compression format and level of the body.
+    pub fn compression(&self) -> Compression {
+        self.0.compression
+    }
+
+    /// The byte order of lengths and numbers in the body.
+    pub fn endianness(&self) -> Endianness {
+        self.0.endianness
+    }
+
+    /// Whether lengths and integers in the body are written as varints.
+    pub fn varint_encoding(&self) -> bool {
+        self.0.varint
+    }
+
+    /// The metadata stored in the header, if the file has any.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.0.metadata.as_ref()
+    }
+
+    /// The checksum at the end of the body, if the file has one.
+    pub fn checksum(&self) -> Option<Checksum> {
+        self.0.checksum
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct FileHeader {
     pub(crate) compression: Compression,
-    pub(crate) version: u8,
+    pub(crate) version: FormatVersion,
     pub(crate) header_name: String,
+    /// Stored as flags after the compression format from version 2 onwards.
+    pub(crate) varint: bool,
+    pub(crate) endianness: Endianness,
+    /// Stored after the flags from version 3 onwards.
+    pub(crate) metadata: Option<Metadata>,
+    /// Stored as flags from version 4 onwards.
+    pub(crate) checksum: Option<Checksum>,
 }
 
 impl Default for FileHeader {
@@ -119,12 +417,72 @@ impl Default for FileHeader {
 }
 
 impl FileHeader {
+    const VARINT_FLAG: u8 = 0x01;
+    const LITTLE_ENDIAN_FLAG: u8 = 0x02;
+    const METADATA_FLAG: u8 = 0x04;
+    const CRC32_FLAG: u8 = 0x08;
+    const XXH3_FLAG: u8 = 0x10;
+
     pub fn new(compression: Compression) -> Self {
-        Self {
+        Self::with_encoding(compression, Encoding::<BigEndian>::new(false))
+    }
+
+    /// Creates a header for a body written with the given encoding, with the oldest version that can record it.
+    pub(crate) fn with_encoding<B: byteorder::ByteOrder>(
+        compression: Compression,
+        encoding: Encoding<B>,
+    ) -> Self {
+        let mut header = Self {
             compression,
-            version: 1,
+            version: FormatVersion::V1,
             header_name: String::from("SBIF"),
+            varint: encoding.varint,
+            endianness: Endianness::of::<B>(),
+            metadata: None,
+            checksum: None,
+        };
+        header.version = header.required_version();
+        header
+    }
+
+    /// Adds metadata to the header, which needs version 3.
+    pub(crate) fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = Some(metadata);
+        self.version = self.version.max(FormatVersion::V3);
+        self
+    }
+
+    /// Adds a checksum to the end of the body, which needs version 4.
+    pub(crate) fn with_checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = Some(checksum);
+        self.version = self.version.max(FormatVersion::V4);
+        self
+    }
+
+    /// The oldest version that can record the flags and metadata of this header.
+    pub(crate) fn required_version(&self) -> FormatVersion {
+        let flags = self.flags();
+        [FormatVersion::V1, FormatVersion::V2, FormatVersion::V3]
+            .into_iter()
+            .find(|version| flags & !version.known_flags() == 0)
+            .unwrap_or(FormatVersion::V4)
+    }
+
+    fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.varint {
+            flags |= Self::VARINT_FLAG;
+        }
+        if self.endianness == Endianness::Little {
+            flags |= Self::LITTLE_ENDIAN_FLAG;
         }
+        if self.metadata.is_some() {
+            flags |= Self::METADATA_FLAG;
+        }
+        if let Some(checksum) = self.checksum {
+            flags |= checksum.flag();
+        }
+        flags
     }
 
     pub fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
@@ -133,7 +491,9 @@ impl FileHeader {
             .write_u16::<ByteOrder>(name_bytes.len() as u16)
             .map_err(Error::IoError)?;
         writer.write(name_bytes).map_err(Error::IoError)?;
-        writer.write_u8(self.version).map_err(Error::IoError)?;
+        writer
+            .write_u8(self.version.into())
+            .map_err(Error::IoError)?;
 
         match self.compression {
             Compression::None => writer.write_u8(0).map_err(Error::IoError)?,
@@ -149,8 +509,23 @@ impl FileHeader {
                 writer.write_u8(3).map_err(Error::IoError)?;
                 writer.write_u32::<ByteOrder>(v).map_err(Error::IoError)?;
             }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(v) => {
+                writer.write_u8(4).map_err(Error::IoError)?;
+                writer.write_i32::<ByteOrder>(v).map_err(Error::IoError)?;
+            }
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => writer.write_u8(5).map_err(Error::IoError)?,
         };
 
+        if self.version >= FormatVersion::V2 {
+            writer.write_u8(self.flags()).map_err(Error::IoError)?;
+        }
+
+        if let Some(metadata) = &self.metadata {
+            metadata.to_writer(writer)?;
+        }
+
         Ok(())
     }
 
@@ -161,27 +536,71 @@ impl FileHeader {
         Ok(buffer)
     }
 
-    pub fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
-        let header_name = {
-            let name_length = reader.read_u16::<ByteOrder>().map_err(Error::IoError)? as usize;
-            let mut buffer = vec![0_u8; name_length];
-            reader.read_exact(&mut buffer).map_err(Error::IoError)?;
-            String::from_utf8(buffer).map_err(Error::FromUtf8Error)?
-        };
+    /// Reads the magic name at the start of the header, which is checked before the rest is read.
+    pub fn read_name<R: Read>(reader: &mut R) -> Result<String> {
+        let name_length = reader.read_u16::<ByteOrder>().map_err(Error::IoError)? as usize;
+        let mut buffer = Vec::new();
+        if reader
+            .take(name_length as u64)
+            .read_to_end(&mut buffer)
+            .map_err(Error::IoError)?
+            < name_length
+        {
+            return Err(Error::IoError(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        String::from_utf8(buffer).map_err(Error::FromUtf8Error)
+    }
+
+    /// Reads the rest of the header after its name.
+    pub fn from_reader<R: Read>(reader: &mut R, header_name: String) -> Result<Self> {
+        let version = FormatVersion::try_from(reader.read_u8().map_err(Error::IoError)?)?;
 
-        let version = reader.read_u8().map_err(Error::IoError)?;
         let compression = match reader.read_u8().map_err(Error::IoError)? {
             0 => Compression::None,
             1 => Compression::Deflate(reader.read_u32::<ByteOrder>().map_err(Error::IoError)?),
             2 => Compression::GZip(reader.read_u32::<ByteOrder>().map_err(Error::IoError)?),
             3 => Compression::ZLib(reader.read_u32::<ByteOrder>().map_err(Error::IoError)?),
+            #[cfg(feature = "zstd")]
+            4 => Compression::Zstd(reader.read_i32::<ByteOrder>().map_err(Error::IoError)?),
+            #[cfg(not(feature = "zstd"))]
+            4 => return Err(Error::UnsupportedCompression("zstd")),
+            #[cfg(feature = "lz4")]
+            5 => Compression::Lz4,
+            #[cfg(not(feature = "lz4"))]
+            5 => return Err(Error::UnsupportedCompression("lz4")),
             v => return Err(Error::InvalidCompression(v)),
         };
 
+        let mut varint = false;
+        let mut endianness = Endianness::Big;
+        let mut metadata = None;
+        let mut checksum = None;
+        if version >= FormatVersion::V2 {
+            let flags = reader.read_u8().map_err(Error::IoError)?;
+            let checksums = Self::CRC32_FLAG | Self::XXH3_FLAG;
+            if flags & !version.known_flags() != 0 || flags & checksums == checksums {
+                return Err(Error::UnsupportedFlags(flags));
+            }
+
+            varint = flags & Self::VARINT_FLAG != 0;
+            if flags & Self::LITTLE_ENDIAN_FLAG != 0 {
+                endianness = Endianness::Little;
+            }
+            if flags & Self::METADATA_FLAG != 0 {
+                metadata = Some(Metadata::from_reader(reader)?);
+            }
+            checksum = Checksum::from_flags(flags)?;
+        }
+
         Ok(Self {
             compression,
             version,
             header_name,
+            varint,
+            endianness,
+            metadata,
+            checksum,
         })
     }
 }
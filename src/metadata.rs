@@ -0,0 +1,221 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+use crate::{ByteOrder, Error, Result};
+
+/// Information about a file that is stored uncompressed in its header, so that it can be read with
+/// [`crate::read_header`] without decoding the body. Written when set in [`crate::SerializerOptions::metadata`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// Identifies the application that wrote the file.
+    pub app_id: String,
+    /// The version of the application's schema that the body was written with.
+    pub schema_version: u32,
+    /// When the file was created, stored to the millisecond. Times before the Unix epoch are not stored.
+    pub created: Option<SystemTime>,
+    /// Free-form key value pairs.
+    pub entries: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    /// Writes the metadata block, which starts with its length so that readers can skip fields added by
+    /// later versions of the format.
+    pub(crate) fn to_writer<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut block = Vec::new();
+        write_string(&mut block, &self.app_id)?;
+        block
+            .write_u32::<ByteOrder>(self.schema_version)
+            .map_err(Error::IoError)?;
+        // Zero marks a file without a creation time.
+        let created = self
+            .created
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_millis() as u64);
+        block
+            .write_u64::<ByteOrder>(created)
+            .map_err(Error::IoError)?;
+        block
+            .write_u32::<ByteOrder>(block_length(
+                self.entries.len(),
+                "Too many metadata entries",
+            )?)
+            .map_err(Error::IoError)?;
+        for (key, value) in &self.entries {
+            write_string(&mut block, key)?;
+            write_string(&mut block, value)?;
+        }
+
+        writer
+            .write_u32::<ByteOrder>(block_length(block.len(), "Metadata block is too long")?)
+            .map_err(Error::IoError)?;
+        writer.write_all(&block).map_err(Error::IoError)
+    }
+
+    pub(crate) fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let length = reader.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
+        let block = read_exact(reader, length)?;
+        let mut block = block.as_slice();
+
+        let app_id = read_string(&mut block)?;
+        let schema_version = block.read_u32::<ByteOrder>().map_err(Error::IoError)?;
+        let created = match block.read_u64::<ByteOrder>().map_err(Error::IoError)? {
+            0 => None,
+            millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+        };
+        let count = block.read_u32::<ByteOrder>().map_err(Error::IoError)?;
+        let mut entries = BTreeMap::new();
+        for _ in 0..count {
+            let key = read_string(&mut block)?;
+            entries.insert(key, read_string(&mut block)?);
+        }
+
+        Ok(Self {
+            app_id,
+            schema_version,
+            created,
+            entries,
+        })
+    }
+}
+
+fn write_string<W: Write>(writer: &mut W, value: &str) -> Result<()> {
+    writer
+        .write_u32::<ByteOrder>(block_length(value.len(), "Metadata string is too long")?)
+        .map_err(Error::IoError)?;
+    writer.write_all(value.as_bytes()).map_err(Error::IoError)
+}
+
+/// Converts a length into the 32 bits that the metadata block stores it in, failing rather than truncating it.
+fn block_length(length: usize, message: &str) -> Result<u32> {
+    u32::try_from(length).map_err(|_| Error::InvalidLength {
+        expected: u32::MAX as usize,
+        actual: length,
+        message: String::from(message),
+    })
+}
+
+fn read_string<R: Read>(reader: &mut R) -> Result<String> {
+    let length = reader.read_u32::<ByteOrder>().map_err(Error::IoError)? as usize;
+    String::from_utf8(read_exact(reader, length)?).map_err(Error::FromUtf8Error)
+}
+
+/// Reads `length` bytes without allocating them up front, as the length has not been validated.
+fn read_exact<R: Read>(reader: &mut R, length: usize) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    if reader
+        .take(length as u64)
+        .read_to_end(&mut buffer)
+        .map_err(Error::IoError)?
+        < length
+    {
+        return Err(Error::IoError(std::io::ErrorKind::UnexpectedEof.into()));
+    }
+
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Serialize;
+
+    use super::*;
+    use crate::{
+        from_slice, read_header, to_bytes_with, Compression, Endianness, FileHeader, FormatVersion,
+        LittleEndian, SbifOptions, Serializer,
+    };
+
+    fn metadata() -> Metadata {
+        Metadata {
+            app_id: String::from("com.example.game"),
+            schema_version: 7,
+            created: Some(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+            entries: BTreeMap::from([
+                (String::from("player"), String::from("Alice")),
+                (String::from("level"), String::from("12")),
+            ]),
+        }
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let mut serializer =
+            Serializer::new_with_metadata(Vec::new(), Compression::GZip(6), metadata()).unwrap();
+        vec![1_u32, 2, 3].serialize(&mut serializer).unwrap();
+        let serialized = serializer.finish().unwrap();
+
+        let header = read_header(serialized.as_slice()).unwrap();
+        assert_eq!(header.version(), FormatVersion::V3);
+        assert_eq!(header.compression(), Compression::GZip(6));
+        assert_eq!(header.metadata(), Some(&metadata()));
+        assert_eq!(from_slice::<Vec<u32>>(&serialized).unwrap(), [1, 2, 3]);
+
+        let options = SbifOptions::new()
+            .metadata(metadata())
+            .byte_order::<LittleEndian>();
+        let serialized = to_bytes_with(&vec![1_u32, 2, 3], &options).unwrap();
+        let header = read_header(serialized.as_slice()).unwrap();
+        assert_eq!(header.metadata(), Some(&metadata()));
+        assert_eq!(header.endianness(), Endianness::Little);
+        assert_eq!(from_slice::<Vec<u32>>(&serialized).unwrap(), [1, 2, 3]);
+        assert!(matches!(
+            to_bytes_with(&1_u8, &options.version(FormatVersion::V2)),
+            Err(Error::InvalidVersion {
+                expected: 3,
+                found: 2
+            })
+        ));
+
+        let plain = crate::to_bytes(&1_u8, Compression::None).unwrap();
+        assert_eq!(read_header(plain.as_slice()).unwrap().metadata(), None);
+    }
+
+    #[test]
+    fn test_metadata_block() {
+        let empty = Metadata::default();
+        let mut block = Vec::new();
+        empty.to_writer(&mut block).unwrap();
+        assert_eq!(
+            block,
+            [0, 0, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(Metadata::from_reader(&mut block.as_slice()).unwrap(), empty);
+
+        // Fields added to the end of the block by later versions are skipped.
+        block[3] += 2;
+        block.extend([0xAB, 0xCD, 0xEF]);
+        let mut reader = block.as_slice();
+        assert_eq!(Metadata::from_reader(&mut reader).unwrap(), empty);
+        assert_eq!(reader, [0xEF]);
+
+        block.truncate(10);
+        assert!(Metadata::from_reader(&mut block.as_slice()).is_err());
+
+        // Lengths that do not fit in the block are rejected rather than truncated.
+        assert_eq!(block_length(u32::MAX as usize, "").unwrap(), u32::MAX);
+        assert!(matches!(
+            block_length(u32::MAX as usize + 1, ""),
+            Err(Error::InvalidLength { .. })
+        ));
+    }
+
+    #[test]
+    fn test_metadata_requires_version_3() {
+        let mut header = FileHeader::new(Compression::None)
+            .with_metadata(Metadata::default())
+            .to_bytes()
+            .unwrap();
+        header.extend([crate::data_ids::U8_ID, 1]);
+        assert_eq!(from_slice::<u8>(&header).unwrap(), 1);
+
+        header[6] = 2;
+        assert!(matches!(
+            from_slice::<u8>(&header),
+            Err(Error::UnsupportedFlags(0x04))
+        ));
+    }
+}
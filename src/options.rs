@@ -0,0 +1,379 @@
+use std::{borrow::Cow, io, marker::PhantomData};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::{
+    read::{IoRead, SliceRead},
+    Checksum, Compression, Deserializer, DeserializerOptions, EnumEncoding, Error, FormatVersion,
+    Header, Metadata, Serializer, SerializerOptions, StructEncoding,
+};
+
+/// A configuration shared by serializers and deserializers, built up from the defaults with its setters.
+/// Serializers write lengths and numbers in the byte order `B`, which is big endian unless changed with
+/// [`SbifOptions::byte_order`].
+///
+/// Example
+/// ```
+/// use serde_sbif::{Compression, LittleEndian, SbifOptions};
+/// let options = SbifOptions::new()
+///     .compression(Compression::None)
+///     .byte_order::<LittleEndian>()
+///     .max_depth(16);
+/// let bytes = serde_sbif::to_bytes_with(&vec![1_u32, 2, 3], &options).unwrap();
+/// let value: Vec<u32> = serde_sbif::from_slice_with(&bytes, &options).unwrap();
+/// assert_eq!(value, [1, 2, 3]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SbifOptions<B: ByteOrder = BigEndian> {
+    compression: Compression,
+    serializer: SerializerOptions,
+    deserializer: DeserializerOptions,
+    byte_order: PhantomData<B>,
+}
+
+impl SbifOptions {
+    /// Creates options with the default compression and settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<B: ByteOrder> SbifOptions<B> {
+    /// Sets the compression used when serializing. Deserializers read the compression from the header.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Sets the header version written by serializers, see [`SerializerOptions::version`].
+    pub fn version(mut self, version: FormatVersion) -> Self {
+        self.serializer.version = Some(version);
+        self
+    }
+
+    /// Sets the byte order of lengths and numbers written by serializers, [`crate::BigEndian`] or
+    /// [`crate::LittleEndian`]. Deserializers read the byte order from the header.
+    pub fn byte_order<O: ByteOrder>(self) -> SbifOptions<O> {
+        SbifOptions {
+            compression: self.compression,
+            serializer: self.serializer,
+            deserializer: self.deserializer,
+            byte_order: PhantomData,
+        }
+    }
+
+    /// Sets the checksum written at the end of the body by serializers.
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.serializer.checksum = Some(checksum);
+        self
+    }
+
+    /// Sets the metadata stored in the header by serializers.
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        self.serializer.metadata = Some(metadata);
+        self
+    }
+
+    /// Sets the magic name written at the start of the header by serializers.
+    pub fn magic(mut self, magic: impl Into<Cow<'static, str>>) -> Self {
+        self.serializer.magic = magic.into();
+        self
+    }
+
+    /// Sets the magic names accepted by deserializers.
+    pub fn accepted_magics<M: Into<Cow<'static, str>>>(
+        mut self,
+        accepted_magics: impl IntoIterator<Item = M>,
+    ) -> Self {
+        self.deserializer.accepted_magics = accepted_magics.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets whether serializers write sequences of a single numeric type as packed sequences in files of
+    /// version 2 or later, which they do by default.
+    pub fn pack_sequences(mut self, pack_sequences: bool) -> Self {
+        self.serializer.pack_sequences = pack_sequences;
+        self
+    }
+
+    /// Sets whether serializers write sequences and tuples of `u8` as bytes, see
+    /// [`SerializerOptions::u8_sequences_as_bytes`].
+    pub fn u8_sequences_as_bytes(mut self, u8_sequences_as_bytes: bool) -> Self {
+        self.serializer.u8_sequences_as_bytes = u8_sequences_as_bytes;
+        self
+    }
+
+    /// Sets whether serializers intern map keys and struct field names.
+    pub fn intern_keys(mut self, intern_keys: bool) -> Self {
+        self.serializer.intern_keys = intern_keys;
+        self
+    }
+
+    /// Sets how serializers write structs and struct variants.
+    pub fn struct_encoding(mut self, struct_encoding: StructEncoding) -> Self {
+        self.serializer.struct_encoding = struct_encoding;
+        self
+    }
+
+    /// Sets how serializers write enum variants.
+    pub fn enum_encoding(mut self, enum_encoding: EnumEncoding) -> Self {
+        self.serializer.enum_encoding = enum_encoding;
+        self
+    }
+
+    /// Sets whether serializers write lengths and integers as variable-length integers.
+    pub fn varint_encoding(mut self, varint_encoding: bool) -> Self {
+        self.serializer.varint_encoding = varint_encoding;
+        self
+    }
+
+    /// Sets whether serializers return [`Error::LengthRequired`] for sequences and maps of unknown length.
+    pub fn require_lengths(mut self, require_lengths: bool) -> Self {
+        self.serializer.require_lengths = require_lengths;
+        self
+    }
+
+    /// Sets the value returned by `is_human_readable` for both serializers and deserializers.
+    pub fn human_readable(mut self, human_readable: bool) -> Self {
+        self.serializer.human_readable = human_readable;
+        self.deserializer.human_readable = human_readable;
+        self
+    }
+
+    /// Sets the maximum length of a single string or byte array that deserializers will read.
+    pub fn max_allocation(mut self, max_allocation: usize) -> Self {
+        self.deserializer.max_allocation = max_allocation;
+        self
+    }
+
+    /// Sets the maximum number of bytes that deserializers will decode, after decompression.
+    pub fn max_total_bytes(mut self, max_total_bytes: usize) -> Self {
+        self.deserializer.max_total_bytes = max_total_bytes;
+        self
+    }
+
+    /// Sets the maximum number of elements in a sequence, tuple or map that deserializers will read.
+    pub fn max_collection_length(mut self, max_collection_length: usize) -> Self {
+        self.deserializer.max_collection_length = max_collection_length;
+        self
+    }
+
+    /// Sets the maximum depth of nested values that deserializers will read.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.deserializer.max_depth = max_depth;
+        self
+    }
+
+    /// Reads the header at the start of a file, failing if its magic name is not one of the accepted magics,
+    /// see [`crate::read_header_with`].
+    pub fn read_header<R: io::Read>(&self, reader: R) -> Result<Header, Error> {
+        crate::read_header_with(reader, &self.deserializer.accepted_magics)
+    }
+
+    pub(crate) fn deserializer_options(&self) -> &DeserializerOptions {
+        &self.deserializer
+    }
+
+    /// Creates a serializer that writes to the given writer.
+    pub fn serializer<W: io::Write>(&self, writer: W) -> Result<Serializer<W, B>, Error> {
+        Serializer::with_byte_order(writer, self.compression, self.serializer.clone())
+    }
+
+    /// Creates a deserializer that reads from the given reader, failing with [`Error::ByteOrderMismatch`]
+    /// if its body is not in the byte order `B`. Use [`crate::from_slice_with`] to read either byte order.
+    pub fn deserializer<R: io::Read>(
+        &self,
+        reader: R,
+    ) -> Result<Deserializer<IoRead<R>, B>, Error> {
+        Deserializer::with_byte_order(reader, self.deserializer.clone())
+    }
+
+    /// Creates a deserializer that reads from a byte slice, see [`Deserializer::from_slice`].
+    pub fn slice_deserializer<'a>(
+        &self,
+        bytes: &'a [u8],
+    ) -> Result<Deserializer<SliceRead<'a>, B>, Error> {
+        Deserializer::from_slice_with_byte_order(bytes, self.deserializer.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::Ipv4Addr};
+
+    use serde::{ser::SerializeSeq, Deserialize, Serialize};
+
+    use super::*;
+    use crate::{data_ids, from_slice_with, to_bytes_with, FileHeader, Limit, LittleEndian};
+
+    #[test]
+    fn test_options_round_trip() {
+        let options = SbifOptions::new()
+            .compression(Compression::None)
+            .byte_order::<LittleEndian>()
+            .enum_encoding(EnumEncoding::Name)
+            .human_readable(false);
+        let value = (
+            Some(Ipv4Addr::LOCALHOST),
+            HashMap::from([(1_u16, -1.5_f64)]),
+        );
+        let bytes = to_bytes_with(&value, &options).unwrap();
+        assert_eq!(
+            from_slice_with::<(Option<Ipv4Addr>, HashMap<u16, f64>)>(&bytes, &options).unwrap(),
+            value
+        );
+
+        let mut deserializer = options.deserializer(bytes.as_slice()).unwrap();
+        let deserialized: (Option<Ipv4Addr>, HashMap<u16, f64>) =
+            Deserialize::deserialize(&mut deserializer).unwrap();
+        assert_eq!(deserialized, value);
+
+        // Addresses are written as four bytes rather than a string when not human readable.
+        let bytes = to_bytes_with(&Ipv4Addr::LOCALHOST, &options).unwrap();
+        assert_eq!(bytes[9], data_ids::TUPLE_ID);
+        assert!(from_slice_with::<Ipv4Addr>(&bytes, &SbifOptions::new()).is_err());
+    }
+
+    #[test]
+    fn test_options_encodings() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Tile {
+            pixels: Vec<u8>,
+            tags: HashMap<String, u32>,
+        }
+
+        let options = SbifOptions::new()
+            .compression(Compression::None)
+            .varint_encoding(true)
+            .struct_encoding(StructEncoding::Positional)
+            .intern_keys(true)
+            .u8_sequences_as_bytes(true);
+        assert_eq!(
+            options.serializer,
+            SerializerOptions {
+                varint_encoding: true,
+                struct_encoding: StructEncoding::Positional,
+                intern_keys: true,
+                u8_sequences_as_bytes: true,
+                ..SerializerOptions::default()
+            }
+        );
+
+        let tiles: Vec<_> = (0..2)
+            .map(|i| Tile {
+                pixels: vec![i, 255],
+                tags: HashMap::from([(String::from("layer"), 300)]),
+            })
+            .collect();
+        let bytes = to_bytes_with(&tiles, &options).unwrap();
+        assert!(options
+            .read_header(bytes.as_slice())
+            .unwrap()
+            .varint_encoding());
+        assert_eq!(
+            from_slice_with::<Vec<Tile>>(&bytes, &options).unwrap(),
+            tiles
+        );
+        // Each tile is a tuple of its pixels as bytes and its tags, whose key is written in full once.
+        assert_eq!(
+            &bytes[9..16],
+            &[
+                data_ids::SEQ_ID,
+                2,
+                data_ids::TUPLE_ID,
+                2,
+                data_ids::BYTES_ID,
+                2,
+                0
+            ]
+        );
+        assert_eq!(
+            bytes.windows(5).filter(|window| window == b"layer").count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_options_magic() {
+        let options = SbifOptions::new()
+            .magic("MYGAME-SAVE")
+            .accepted_magics([String::from("MYGAME-SAVE")]);
+        let bytes = to_bytes_with(&1_u8, &options).unwrap();
+        assert_eq!(&bytes[..13], b"\0\x0BMYGAME-SAVE");
+        assert_eq!(
+            options.read_header(bytes.as_slice()).unwrap().name(),
+            "MYGAME-SAVE"
+        );
+        assert!(matches!(
+            SbifOptions::new().read_header(bytes.as_slice()),
+            Err(Error::InvalidHeader { .. })
+        ));
+        assert_eq!(from_slice_with::<u8>(&bytes, &options).unwrap(), 1);
+        assert!(from_slice_with::<u8>(&bytes, &SbifOptions::new()).is_err());
+        assert!(from_slice_with::<u8>(
+            &to_bytes_with(&1_u8, &SbifOptions::new()).unwrap(),
+            &options
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_options_limits() {
+        let options = SbifOptions::new().max_collection_length(2);
+        let bytes = to_bytes_with(&vec![1_u8, 2, 3], &options).unwrap();
+        assert!(matches!(
+            from_slice_with::<Vec<u8>>(&bytes, &options).map_err(Error::into_inner),
+            Err(Error::LimitExceeded {
+                limit: Limit::CollectionLength,
+                maximum: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_options_require_lengths() {
+        struct Unsized;
+
+        impl Serialize for Unsized {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut seq = serializer.serialize_seq(None)?;
+                seq.serialize_element(&1_u8)?;
+                seq.end()
+            }
+        }
+
+        assert!(to_bytes_with(&Unsized, &SbifOptions::new()).is_ok());
+        assert!(matches!(
+            to_bytes_with(&Unsized, &SbifOptions::new().require_lengths(true)),
+            Err(Error::LengthRequired)
+        ));
+    }
+
+    #[test]
+    fn test_options_version() {
+        let options = SbifOptions::new().compression(Compression::None);
+        let bytes = to_bytes_with(&1_u8, &options.clone().version(FormatVersion::V4)).unwrap();
+        let mut header = FileHeader::new(Compression::None);
+        header.version = FormatVersion::V4;
+        let header = header.to_bytes().unwrap();
+        assert_eq!(&bytes[..9], header.as_slice());
+        assert_eq!(from_slice_with::<u8>(&bytes, &options).unwrap(), 1);
+
+        let bytes = to_bytes_with(&1_u8, &options.clone().version(FormatVersion::V1)).unwrap();
+        assert_eq!(bytes[6], 1);
+        assert_eq!(&bytes[8..], &[data_ids::U8_ID, 1]);
+
+        assert!(matches!(
+            to_bytes_with(
+                &1_u8,
+                &options
+                    .byte_order::<LittleEndian>()
+                    .version(FormatVersion::V1)
+            ),
+            Err(Error::InvalidVersion {
+                expected: 2,
+                found: 1
+            })
+        ));
+    }
+}
@@ -0,0 +1,372 @@
+use std::io::{self, BufRead as _, Read as _};
+
+use byteorder::ReadBytesExt;
+use flate2::bufread::{DeflateDecoder, GzDecoder, ZlibDecoder};
+
+use crate::{
+    checksum::{Checksum, Hasher},
+    Compression,
+};
+
+/// A block of bytes read from the input, either borrowed from the input itself or copied into a
+/// scratch buffer.
+#[doc(hidden)]
+pub enum Reference<'de, 'a> {
+    Borrowed(&'de [u8]),
+    Copied(&'a [u8]),
+}
+
+/// The input source of a [`crate::Deserializer`]. This trait is sealed and is implemented by
+/// [`IoRead`] and [`SliceRead`].
+pub trait Read<'de>: io::Read + private::Sealed {
+    #[doc(hidden)]
+    fn peek_u8(&mut self) -> io::Result<u8>;
+
+    #[doc(hidden)]
+    fn read_slice<'a>(
+        &'a mut self,
+        length: usize,
+        scratch: &'a mut Vec<u8>,
+    ) -> io::Result<Reference<'de, 'a>>;
+
+    #[doc(hidden)]
+    fn byte_offset(&self) -> usize;
+
+    /// The checksum of the data read so far, if the file has a checksum.
+    #[doc(hidden)]
+    fn checksum(&self) -> Option<u64>;
+
+    /// Whether the input has no data after the body, which for a compressed body is the data after the end
+    /// of its compressed stream. This is only meaningful once the body has been read to its end.
+    #[doc(hidden)]
+    fn input_at_end(&mut self) -> io::Result<bool>;
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// Decompresses data from a buffered reader. The decoders stop at the end of their compressed stream,
+/// leaving any data that follows it in the buffered reader.
+enum Reader<B: io::BufRead> {
+    None(B),
+    Deflate(DeflateDecoder<B>),
+    GZip(GzDecoder<B>),
+    ZLib(ZlibDecoder<B>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::read::Decoder<'static, B>),
+    #[cfg(feature = "lz4")]
+    Lz4(lz4_flex::frame::FrameDecoder<B>),
+}
+
+impl<B: io::BufRead> Reader<B> {
+    fn get_ref(&self) -> &B {
+        match self {
+            Self::None(r) => r,
+            Self::Deflate(r) => r.get_ref(),
+            Self::GZip(r) => r.get_ref(),
+            Self::ZLib(r) => r.get_ref(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(r) => r.get_ref(),
+            #[cfg(feature = "lz4")]
+            Self::Lz4(r) => r.get_ref(),
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut B {
+        match self {
+            Self::None(r) => r,
+            Self::Deflate(r) => r.get_mut(),
+            Self::GZip(r) => r.get_mut(),
+            Self::ZLib(r) => r.get_mut(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(r) => r.get_mut(),
+            #[cfg(feature = "lz4")]
+            Self::Lz4(r) => r.get_mut(),
+        }
+    }
+
+    fn into_inner(self) -> B {
+        match self {
+            Self::None(r) => r,
+            Self::Deflate(r) => r.into_inner(),
+            Self::GZip(r) => r.into_inner(),
+            Self::ZLib(r) => r.into_inner(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(r) => r.finish(),
+            #[cfg(feature = "lz4")]
+            Self::Lz4(r) => r.into_inner(),
+        }
+    }
+}
+
+impl<B: io::BufRead> io::Read for Reader<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::None(r) => r.read(buf),
+            Self::Deflate(r) => r.read(buf),
+            Self::GZip(r) => r.read(buf),
+            Self::ZLib(r) => r.read(buf),
+            // The zstd decoder reports an error rather than reading nothing into an empty buffer.
+            #[cfg(feature = "zstd")]
+            Self::Zstd(_) if buf.is_empty() => Ok(0),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(r) => r.read(buf),
+            #[cfg(feature = "lz4")]
+            Self::Lz4(r) => r.read(buf),
+        }
+    }
+}
+
+/// Reads SBIF data from an [`io::Read`], decompressing it as required. Strings and bytes are always
+/// copied out of the stream.
+pub struct IoRead<R: io::Read> {
+    reader: Reader<io::BufReader<R>>,
+    /// A decompressed byte that has been peeked at but not read. Uncompressed bytes are peeked at in the
+    /// buffered reader instead, so that they are kept by [`IoRead::into_inner`].
+    peeked: Option<u8>,
+    offset: usize,
+    hasher: Option<Hasher>,
+}
+
+impl<R: io::Read> IoRead<R> {
+    pub(crate) fn new(
+        reader: R,
+        compression: Compression,
+        checksum: Option<Checksum>,
+    ) -> io::Result<Self> {
+        Self::from_buf_reader(io::BufReader::new(reader), compression, checksum)
+    }
+
+    pub(crate) fn from_buf_reader(
+        reader: io::BufReader<R>,
+        compression: Compression,
+        checksum: Option<Checksum>,
+    ) -> io::Result<Self> {
+        let reader = match compression {
+            Compression::None => Reader::None(reader),
+            Compression::Deflate(_) => Reader::Deflate(DeflateDecoder::new(reader)),
+            Compression::GZip(_) => Reader::GZip(GzDecoder::new(reader)),
+            Compression::ZLib(_) => Reader::ZLib(ZlibDecoder::new(reader)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(_) => {
+                Reader::Zstd(zstd::stream::read::Decoder::with_buffer(reader)?.single_frame())
+            }
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Reader::Lz4(lz4_flex::frame::FrameDecoder::new(reader)),
+        };
+
+        Ok(Self {
+            reader,
+            peeked: None,
+            offset: 0,
+            hasher: checksum.map(Hasher::new),
+        })
+    }
+
+    /// Returns the buffered reader, positioned after the data that has been read, so that data stored after
+    /// the document can be read from it.
+    ///
+    /// A compressed body is positioned wherever its decoder stopped, which is before the end of the compressed
+    /// stream unless the body has been read up to it, as [`crate::Deserializer::end`] does.
+    pub fn into_inner(self) -> io::BufReader<R> {
+        self.reader.into_inner()
+    }
+
+    fn get_ref(&self) -> &io::BufReader<R> {
+        self.reader.get_ref()
+    }
+}
+
+impl<R: io::Read> io::Read for IoRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match (self.peeked.take(), buf.first_mut()) {
+            (Some(byte), Some(first)) => {
+                *first = byte;
+                1
+            }
+            (peeked, _) => {
+                self.peeked = peeked;
+                self.reader.read(buf)?
+            }
+        };
+        self.offset += read;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+impl<R: io::Read> private::Sealed for IoRead<R> {}
+
+impl<'de, R: io::Read> Read<'de> for IoRead<R> {
+    fn peek_u8(&mut self) -> io::Result<u8> {
+        if let Reader::None(reader) = &mut self.reader {
+            return reader
+                .fill_buf()?
+                .first()
+                .copied()
+                .ok_or_else(|| io::ErrorKind::UnexpectedEof.into());
+        }
+
+        match self.peeked {
+            Some(byte) => Ok(byte),
+            None => {
+                let byte = self.reader.read_u8()?;
+                self.peeked = Some(byte);
+                Ok(byte)
+            }
+        }
+    }
+
+    fn read_slice<'a>(
+        &'a mut self,
+        length: usize,
+        scratch: &'a mut Vec<u8>,
+    ) -> io::Result<Reference<'de, 'a>> {
+        // Grow the buffer as data arrives rather than trusting the length up front.
+        scratch.clear();
+        if self.take(length as u64).read_to_end(scratch)? < length {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(Reference::Copied(scratch))
+    }
+
+    fn byte_offset(&self) -> usize {
+        self.offset
+    }
+
+    fn checksum(&self) -> Option<u64> {
+        self.hasher.as_ref().map(Hasher::value)
+    }
+
+    fn input_at_end(&mut self) -> io::Result<bool> {
+        Ok(self.peeked.is_none() && self.reader.get_mut().fill_buf()?.is_empty())
+    }
+}
+
+enum SliceSource<'a> {
+    Borrowed(&'a [u8]),
+    Decoded(Box<IoRead<&'a [u8]>>),
+}
+
+/// Reads SBIF data from a byte slice. Uncompressed strings and bytes are borrowed from the slice,
+/// compressed data is decoded and copied in the same way as [`IoRead`].
+pub struct SliceRead<'a> {
+    source: SliceSource<'a>,
+    /// The body of the file, which decoded data is read from.
+    input: &'a [u8],
+    offset: usize,
+    /// Hashes borrowed data, decoded data is hashed by its reader.
+    hasher: Option<Hasher>,
+}
+
+impl<'a> SliceRead<'a> {
+    pub(crate) fn new(
+        slice: &'a [u8],
+        compression: Compression,
+        checksum: Option<Checksum>,
+    ) -> io::Result<Self> {
+        let (source, hasher) = match compression {
+            Compression::None => (SliceSource::Borrowed(slice), checksum.map(Hasher::new)),
+            compression => (
+                SliceSource::Decoded(Box::new(IoRead::new(slice, compression, checksum)?)),
+                None,
+            ),
+        };
+
+        Ok(Self {
+            source,
+            input: slice,
+            offset: 0,
+            hasher,
+        })
+    }
+
+    /// Returns the part of the slice after the data that has been read, so that data stored after the
+    /// document can be read from it. A compressed body is positioned as described in [`IoRead::into_inner`].
+    pub fn remaining(&self) -> &'a [u8] {
+        match &self.source {
+            SliceSource::Borrowed(slice) => slice,
+            SliceSource::Decoded(reader) => {
+                let buffered = reader.get_ref();
+                let unread = buffered.buffer().len() + buffered.get_ref().len();
+                &self.input[self.input.len() - unread..]
+            }
+        }
+    }
+}
+
+impl<'a> io::Read for SliceRead<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match &mut self.source {
+            SliceSource::Borrowed(slice) => slice.read(buf)?,
+            SliceSource::Decoded(reader) => reader.read(buf)?,
+        };
+
+        self.offset += read;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+impl<'a> private::Sealed for SliceRead<'a> {}
+
+impl<'a> Read<'a> for SliceRead<'a> {
+    fn peek_u8(&mut self) -> io::Result<u8> {
+        match &mut self.source {
+            SliceSource::Borrowed(slice) => slice
+                .first()
+                .copied()
+                .ok_or_else(|| io::ErrorKind::UnexpectedEof.into()),
+            SliceSource::Decoded(reader) => Read::peek_u8(reader.as_mut()),
+        }
+    }
+
+    fn read_slice<'s>(
+        &'s mut self,
+        length: usize,
+        scratch: &'s mut Vec<u8>,
+    ) -> io::Result<Reference<'a, 's>> {
+        let reference = match &mut self.source {
+            SliceSource::Borrowed(slice) => {
+                if slice.len() < length {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+
+                let (bytes, rest) = slice.split_at(length);
+                *slice = rest;
+                if let Some(hasher) = &mut self.hasher {
+                    hasher.update(bytes);
+                }
+                Reference::Borrowed(bytes)
+            }
+            SliceSource::Decoded(reader) => reader.read_slice(length, scratch)?,
+        };
+
+        self.offset += length;
+        Ok(reference)
+    }
+
+    fn byte_offset(&self) -> usize {
+        self.offset
+    }
+
+    fn checksum(&self) -> Option<u64> {
+        match &self.source {
+            SliceSource::Borrowed(_) => self.hasher.as_ref().map(Hasher::value),
+            SliceSource::Decoded(reader) => Read::checksum(reader.as_ref()),
+        }
+    }
+
+    fn input_at_end(&mut self) -> io::Result<bool> {
+        match &mut self.source {
+            SliceSource::Borrowed(slice) => Ok(slice.is_empty()),
+            SliceSource::Decoded(reader) => reader.input_at_end(),
+        }
+    }
+}
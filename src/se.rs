@@ -1,22 +1,34 @@
-use std::io::Write;
+use std::{borrow::Cow, collections::HashMap, fmt, io::Write};
 
-use byteorder::WriteBytesExt;
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
 use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
-use serde::Serialize;
+use serde::ser::Impossible;
 
-use crate::{ByteOrder, Compression, Error, FileHeader};
+use crate::{
+    checksum::{Checksum, Hasher},
+    encoding::Encoding,
+    Compression, Error, FileHeader, FormatVersion, Metadata, SbifOptions,
+};
 
 /// Serializes a value into a byte vector.
 pub fn to_bytes<T: serde::Serialize>(
     value: &T,
     compression: Compression,
 ) -> Result<Vec<u8>, Error> {
-    let mut buffer = Vec::new();
-    let mut serializer = Serializer::new(&mut buffer, compression)?;
+    let mut serializer = Serializer::new(Vec::new(), compression)?;
     value.serialize(&mut serializer)?;
-    drop(serializer);
+    serializer.finish()
+}
 
-    Ok(buffer)
+/// Serializes a value into a byte vector using the compression, byte order and serializer settings of the
+/// given options.
+pub fn to_bytes_with<T: serde::Serialize>(
+    value: &T,
+    options: &SbifOptions<impl ByteOrder>,
+) -> Result<Vec<u8>, Error> {
+    let mut serializer = options.serializer(Vec::new())?;
+    value.serialize(&mut serializer)?;
+    serializer.finish()
 }
 
 /// Serializes a value into a writer.
@@ -27,7 +39,7 @@ pub fn to_writer<W: Write, T: serde::Serialize>(
 ) -> Result<(), Error> {
     let mut serializer = Serializer::new(writer, compression)?;
     value.serialize(&mut serializer)?;
-    drop(serializer);
+    serializer.finish()?;
 
     Ok(())
 }
@@ -37,6 +49,33 @@ enum Writer<W: Write> {
     Deflate(DeflateEncoder<W>),
     GZip(GzEncoder<W>),
     ZLib(ZlibEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::Encoder<'static, W>),
+    #[cfg(feature = "lz4")]
+    Lz4(lz4_flex::frame::FrameEncoder<W>),
+    /// The writer has been taken by [`Serializer::finish`].
+    Finished,
+}
+
+impl<W: Write> Writer<W> {
+    /// Writes any data the compression format requires at the end of the stream, flushes it and
+    /// returns the underlying writer.
+    fn finish(self) -> std::io::Result<W> {
+        let mut writer = match self {
+            Self::None(w) => w,
+            Self::Deflate(w) => w.finish()?,
+            Self::GZip(w) => w.finish()?,
+            Self::ZLib(w) => w.finish()?,
+            #[cfg(feature = "zstd")]
+            Self::Zstd(w) => w.finish()?,
+            #[cfg(feature = "lz4")]
+            Self::Lz4(w) => w.finish()?,
+            Self::Finished => unreachable!("the serializer has already been finished"),
+        };
+
+        writer.flush()?;
+        Ok(writer)
+    }
 }
 
 impl<W: Write> Write for Writer<W> {
@@ -46,6 +85,11 @@ impl<W: Write> Write for Writer<W> {
             Self::Deflate(w) => w.write(buf),
             Self::GZip(w) => w.write(buf),
             Self::ZLib(w) => w.write(buf),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(w) => w.write(buf),
+            #[cfg(feature = "lz4")]
+            Self::Lz4(w) => w.write(buf),
+            Self::Finished => unreachable!("the serializer has already been finished"),
         }
     }
 
@@ -55,32 +99,281 @@ impl<W: Write> Write for Writer<W> {
             Self::Deflate(w) => w.flush(),
             Self::GZip(w) => w.flush(),
             Self::ZLib(w) => w.flush(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(w) => w.flush(),
+            #[cfg(feature = "lz4")]
+            Self::Lz4(w) => w.flush(),
+            Self::Finished => unreachable!("the serializer has already been finished"),
+        }
+    }
+}
+
+/// The destination of serialized data. Containers whose length is not known up front are written
+/// to a buffer so that their length can be written before their contents.
+struct Output<W: Write> {
+    writer: Writer<W>,
+    buffers: Vec<Vec<u8>>,
+    /// Hashes the data passed to the writer if the file has a checksum.
+    hasher: Option<Hasher>,
+}
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.buffers.last_mut() {
+            Some(buffer) => buffer.write(buf),
+            None => {
+                let written = self.writer.write(buf)?;
+                if let Some(hasher) = &mut self.hasher {
+                    hasher.update(&buf[..written]);
+                }
+                Ok(written)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// How a [`Serializer`] writes structs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StructEncoding {
+    /// Structs are written as maps from field names to values.
+    #[default]
+    Map,
+    /// Structs are written as tuples of their values in declaration order, without the field names.
+    /// Fields cannot be skipped, and the struct must be deserialized with the same fields in the same order.
+    /// This needs version 2, as version 1 struct variants always hold their field names.
+    Positional,
+}
+
+/// How a [`Serializer`] identifies enum variants.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EnumEncoding {
+    /// Variants are written as their index, which changes if variants are reordered or inserted.
+    #[default]
+    Index,
+    /// Variants are written as their name, so variants can be reordered and unknown variants can be
+    /// handled with `#[serde(other)]`.
+    Name,
+}
+
+/// Optional changes to the data written by a [`Serializer`]. The output can be read by any
+/// [`crate::Deserializer`] regardless of the options used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerializerOptions {
+    /// Writes sequences whose elements are all the same numeric type, such as `Vec<f32>`, as packed
+    /// sequences that store the element type once rather than before every element. This is the default,
+    /// and applies to files of version 2 or later, as version 1 files cannot hold packed sequences.
+    pub pack_sequences: bool,
+    /// Writes sequences and tuples of `u8`, such as `Vec<u8>` and `[u8; N]`, as bytes rather than as
+    /// sequences. Such values can then only be deserialized by types that accept bytes or a sequence. This
+    /// excludes `Vec<u8>` and `[u8; N]` in untagged and internally tagged enums and in flattened structs,
+    /// which read their contents through `deserialize_any` and fail on bytes where a sequence is expected.
+    pub u8_sequences_as_bytes: bool,
+    /// Writes each distinct map key and struct field name in full the first time it is serialized and
+    /// as a reference to that occurrence afterwards. Every distinct key is kept in memory while serializing
+    /// and deserializing, and interned keys cannot be borrowed as `&str`.
+    pub intern_keys: bool,
+    /// How structs and struct variants are written.
+    pub struct_encoding: StructEncoding,
+    /// How enum variants are written. Variants without a name, such as those of a [`crate::Value`], are
+    /// always written by index.
+    pub enum_encoding: EnumEncoding,
+    /// Writes lengths and integers as variable-length integers, so that small values take fewer bytes.
+    /// This is recorded in a version 2 header, which older versions of this crate cannot read.
+    pub varint_encoding: bool,
+    /// Returns [`Error::LengthRequired`] for sequences and maps whose length is not known in advance
+    /// instead of buffering their contents until they end.
+    pub require_lengths: bool,
+    /// The value returned by `is_human_readable`, which some types use to choose between a readable
+    /// and a compact representation. It must match the value used when deserializing.
+    pub human_readable: bool,
+    /// The format version to write. By default the oldest version that can hold the data written with the other
+    /// options is used, which is version 1 unless they need a later one. Version 1 files can be read by older
+    /// versions of this crate, but cannot hold packed sequences, interned keys, named variants, positional
+    /// structs or 128-bit integers, which return [`Error::InvalidVersion`], so 128-bit integers need a later
+    /// version to be set. The tuple and struct variants of version 1 files can also not be read by
+    /// self-describing types, such as [`crate::Value`] and untagged enums.
+    pub version: Option<FormatVersion>,
+    /// Writes a checksum of the uncompressed body when [`Serializer::finish`] is called, which is verified by the
+    /// deserializer at the end of the body. This is recorded in a version 4 header. A serializer that is dropped
+    /// without being finished writes no checksum, so that a partial body fails verification.
+    pub checksum: Option<Checksum>,
+    /// The magic name at the start of the header, which lets applications brand their files. Files with a
+    /// name other than "SBIF" can only be read by deserializers that list it in
+    /// [`crate::DeserializerOptions::accepted_magics`].
+    pub magic: Cow<'static, str>,
+    /// Information about the file stored in the header, where it can be read with [`crate::read_header`]
+    /// without decompressing the body. This is recorded in a version 3 header.
+    pub metadata: Option<Metadata>,
+}
+
+impl SerializerOptions {
+    /// The oldest version that knows the data ids written with these options.
+    fn required_version(&self) -> FormatVersion {
+        // Sequences are only packed in files that can hold them, and the fields of version 1 struct
+        // variants are always written with their names.
+        if self.intern_keys
+            || self.enum_encoding == EnumEncoding::Name
+            || self.struct_encoding == StructEncoding::Positional
+        {
+            FormatVersion::V2
+        } else {
+            FormatVersion::V1
+        }
+    }
+}
+
+impl Default for SerializerOptions {
+    fn default() -> Self {
+        Self {
+            pack_sequences: true,
+            u8_sequences_as_bytes: false,
+            intern_keys: false,
+            struct_encoding: StructEncoding::default(),
+            enum_encoding: EnumEncoding::default(),
+            varint_encoding: false,
+            require_lengths: false,
+            human_readable: true,
+            version: None,
+            magic: Cow::Borrowed("SBIF"),
+            checksum: None,
+            metadata: None,
         }
     }
 }
 
-/// Serializer for SBIF format.
-pub struct Serializer<W: Write>(Writer<W>);
+/// Serializer for SBIF format, which writes lengths and numbers in the byte order `B`.
+pub struct Serializer<W: Write, B: ByteOrder = BigEndian> {
+    output: Output<W>,
+    options: SerializerOptions,
+    /// Copied from the header, as it decides how variants are written.
+    version: FormatVersion,
+    encoding: Encoding<B>,
+    /// The index of each string written to the string table so far.
+    strings: HashMap<String, u32>,
+    /// Whether a string serialized now is a key that should be interned.
+    key: bool,
+}
 
 impl<W: Write> Serializer<W> {
     /// Creates a new serializer from a writer. The serializer will automatically write the header to the writer based on the compression type.
-    /// 
+    /// Call [`Serializer::finish`] once the value has been serialized to complete the compressed stream.
+    ///
     /// Example:
     /// ```
     /// use serde_sbif::{Serializer, Compression};
     /// fn serialize_to_bytes<T: serde::Serialize>(value: &T) -> Vec<u8> {
-    ///     let mut buffer = Vec::new();
-    /// 
-    ///     {
-    ///         let mut serializer = Serializer::new(&mut buffer, Compression::default()).unwrap();
-    ///         value.serialize(&mut serializer).unwrap();
-    ///     }
-    /// 
-    ///     buffer
+    ///     let mut serializer = Serializer::new(Vec::new(), Compression::default()).unwrap();
+    ///     value.serialize(&mut serializer).unwrap();
+    ///     serializer.finish().unwrap()
     /// }
     /// ```
-    pub fn new(mut writer: W, compression: Compression) -> Result<Self, Error> {
-        FileHeader::new(compression).to_writer(&mut writer)?;
+    pub fn new(writer: W, compression: Compression) -> Result<Self, Error> {
+        Self::with_options(writer, compression, SerializerOptions::default())
+    }
+
+    /// Creates a new serializer from a writer that writes data according to the given options, with big
+    /// endian lengths and numbers. See [`Serializer::with_byte_order`] for little endian.
+    pub fn with_options(
+        writer: W,
+        compression: Compression,
+        options: SerializerOptions,
+    ) -> Result<Self, Error> {
+        Self::with_byte_order(writer, compression, options)
+    }
+
+    /// Creates a new serializer from a writer that stores the given metadata in a version 3 header, where
+    /// it can be read with [`crate::read_header`] without decompressing the body.
+    ///
+    /// Example:
+    /// ```
+    /// use serde::Serialize;
+    /// use serde_sbif::{Compression, Metadata, Serializer};
+    /// let metadata = Metadata {
+    ///     app_id: String::from("com.example.game"),
+    ///     schema_version: 3,
+    ///     ..Metadata::default()
+    /// };
+    /// let mut serializer = Serializer::new_with_metadata(Vec::new(), Compression::default(), metadata).unwrap();
+    /// "save data".serialize(&mut serializer).unwrap();
+    /// let bytes = serializer.finish().unwrap();
+    ///
+    /// let header = serde_sbif::read_header(bytes.as_slice()).unwrap();
+    /// assert_eq!(header.metadata().unwrap().schema_version, 3);
+    /// ```
+    pub fn new_with_metadata(
+        writer: W,
+        compression: Compression,
+        metadata: Metadata,
+    ) -> Result<Self, Error> {
+        let options = SerializerOptions {
+            metadata: Some(metadata),
+            ..SerializerOptions::default()
+        };
+        Self::with_options(writer, compression, options)
+    }
+}
+
+impl<W: Write, B: ByteOrder> Serializer<W, B> {
+    /// Creates a new serializer from a writer that writes lengths and numbers in the byte order `B` and data
+    /// according to the given options. Little endian is recorded in a version 2 header, and is picked up by
+    /// [`crate::from_slice`] and [`crate::from_reader`].
+    ///
+    /// Example:
+    /// ```
+    /// use serde::Serialize;
+    /// use serde_sbif::{Compression, Endianness, LittleEndian, Serializer, SerializerOptions};
+    /// let mut serializer = Serializer::<_, LittleEndian>::with_byte_order(
+    ///     Vec::new(),
+    ///     Compression::None,
+    ///     SerializerOptions::default(),
+    /// )
+    /// .unwrap();
+    /// 1_u32.serialize(&mut serializer).unwrap();
+    /// let bytes = serializer.finish().unwrap();
+    ///
+    /// assert_eq!(serde_sbif::read_header(bytes.as_slice()).unwrap().endianness(), Endianness::Little);
+    /// assert_eq!(serde_sbif::from_slice::<u32>(&bytes).unwrap(), 1);
+    /// ```
+    pub fn with_byte_order(
+        mut writer: W,
+        compression: Compression,
+        options: SerializerOptions,
+    ) -> Result<Self, Error> {
+        let encoding = Encoding::new(options.varint_encoding);
+        if options.magic.len() > u16::MAX as usize {
+            return Err(Error::InvalidLength {
+                expected: u16::MAX as usize,
+                actual: options.magic.len(),
+                message: String::from("Magic name is too long"),
+            });
+        }
+
+        let mut header = FileHeader::with_encoding(compression, encoding);
+        header.header_name = options.magic.to_string();
+        if let Some(metadata) = &options.metadata {
+            header = header.with_metadata(metadata.clone());
+        }
+        if let Some(checksum) = options.checksum {
+            header = header.with_checksum(checksum);
+        }
+        let required = header.required_version().max(options.required_version());
+        header.version = match options.version {
+            Some(version) if version < required => {
+                return Err(Error::InvalidVersion {
+                    expected: required.into(),
+                    found: version.into(),
+                });
+            }
+            Some(version) => version,
+            None => required,
+        };
+
+        let version = header.version;
+        header.to_writer(&mut writer)?;
         let writer: Writer<W> = match compression {
             Compression::None => Writer::None(writer),
             Compression::Deflate(v) => {
@@ -92,627 +385,1839 @@ impl<W: Write> Serializer<W> {
             Compression::ZLib(v) => {
                 Writer::ZLib(ZlibEncoder::new(writer, flate2::Compression::new(v)))
             }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(v) => {
+                Writer::Zstd(zstd::stream::Encoder::new(writer, v).map_err(Error::IoError)?)
+            }
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Writer::Lz4(lz4_flex::frame::FrameEncoder::new(writer)),
         };
 
-        Ok(Self(writer))
+        Ok(Self {
+            output: Output {
+                writer,
+                buffers: Vec::new(),
+                hasher: options.checksum.map(Hasher::new),
+            },
+            options,
+            version,
+            encoding,
+            strings: HashMap::new(),
+            key: false,
+        })
     }
-}
 
-impl<'a, W: Write> serde::ser::Serializer for &'a mut Serializer<W> {
-    type Ok = ();
-    type Error = Error;
+    /// Writes the checksum, completes the compressed stream, flushes it and returns the underlying writer.
+    /// A serializer that is dropped instead completes the stream without a checksum, and errors that occur
+    /// while doing so are lost.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.write_checksum()?;
+        std::mem::replace(&mut self.output.writer, Writer::Finished)
+            .finish()
+            .map_err(Error::IoError)
+    }
 
-    type SerializeSeq = Self;
-    type SerializeTuple = Self;
-    type SerializeTupleStruct = Self;
-    type SerializeTupleVariant = Self;
-    type SerializeMap = Self;
-    type SerializeStruct = Self;
-    type SerializeStructVariant = Self;
+    /// Writes the checksum of the body to the end of it, if the header promised one.
+    fn write_checksum(&mut self) -> Result<(), Error> {
+        if let (Some(hasher), Some(checksum)) = (self.output.hasher.take(), self.options.checksum) {
+            self.output
+                .writer
+                .write_u8(crate::data_ids::CHECKSUM_ID)
+                .map_err(Error::IoError)?;
+            checksum
+                .write_value(&mut self.output.writer, hasher.value())
+                .map_err(Error::IoError)?;
+        }
 
-    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::BOOL_ID)
-            .map_err(Error::IoError)?;
-        self.0
-            .write_u8(if v { 1 } else { 0 })
-            .map_err(Error::IoError)?;
         Ok(())
     }
 
-    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::I8_ID)
-            .map_err(Error::IoError)?;
-        self.0.write_i8(v).map_err(Error::IoError)?;
-        Ok(())
+    /// Equivalent to [`Serializer::finish`].
+    pub fn into_inner(self) -> Result<W, Error> {
+        self.finish()
     }
 
-    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::I16_ID)
-            .map_err(Error::IoError)?;
-        self.0.write_i16::<ByteOrder>(v).map_err(Error::IoError)?;
-        Ok(())
+    /// Serializes a field of a struct or struct variant, omitting its name if structs are positional.
+    fn serialize_struct_field<T: ?Sized + serde::Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        if self.options.struct_encoding == StructEncoding::Map {
+            self.serialize_key(key)?;
+        }
+
+        value.serialize(self)
     }
 
-    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::I32_ID)
-            .map_err(Error::IoError)?;
-        self.0.write_i32::<ByteOrder>(v).map_err(Error::IoError)?;
-        Ok(())
+    /// Fails if a field is skipped from a positional struct, as that would shift the fields after it.
+    fn skip_struct_field(&mut self, key: &'static str) -> Result<(), Error> {
+        match self.options.struct_encoding {
+            StructEncoding::Map => Ok(()),
+            StructEncoding::Positional => Err(Error::SkippedField(key)),
+        }
     }
 
-    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::I64_ID)
-            .map_err(Error::IoError)?;
-        self.0.write_i64::<ByteOrder>(v).map_err(Error::IoError)?;
-        Ok(())
+    /// Writes the id of an enum variant followed by its name or index.
+    fn serialize_variant(
+        &mut self,
+        is_unit: bool,
+        variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        if self.options.enum_encoding == EnumEncoding::Name && !variant.is_empty() {
+            let id = match is_unit {
+                true => crate::data_ids::NAMED_UNIT_VARIANT_ID,
+                false => crate::data_ids::NAMED_ENUM_VARIANT_ID,
+            };
+            self.output.write_u8(id).map_err(Error::IoError)?;
+            return self.serialize_key(variant);
+        }
+
+        let id = match is_unit {
+            true => crate::data_ids::UNIT_VARIANT_ID,
+            false => crate::data_ids::ENUM_VARIANT_ID,
+        };
+        self.output.write_u8(id).map_err(Error::IoError)?;
+        self.encoding
+            .write_u32(&mut self.output, variant_index)
+            .map_err(Error::IoError)
     }
 
-    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::U8_ID)
-            .map_err(Error::IoError)?;
-        self.0.write_u8(v).map_err(Error::IoError)?;
-        Ok(())
+    /// Serializes a map key or field name, interning it if enabled.
+    fn serialize_key<T: ?Sized + serde::Serialize>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = self.options.intern_keys;
+        let result = key.serialize(&mut *self);
+        self.key = false;
+        result
     }
 
-    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::U16_ID)
+    /// Writes a reference to a string that has already been written, or writes the string and adds it to
+    /// the string table.
+    fn serialize_interned(&mut self, v: &str) -> Result<(), Error> {
+        if let Some(&index) = self.strings.get(v) {
+            self.output
+                .write_u8(crate::data_ids::STR_REF_ID)
+                .map_err(Error::IoError)?;
+            return self
+                .encoding
+                .write_u32(&mut self.output, index)
+                .map_err(Error::IoError);
+        }
+
+        self.strings.insert(v.to_owned(), self.strings.len() as u32);
+        self.output
+            .write_u8(crate::data_ids::INTERNED_STR_ID)
             .map_err(Error::IoError)?;
-        self.0.write_u16::<ByteOrder>(v).map_err(Error::IoError)?;
-        Ok(())
+        self.encoding
+            .write_length(&mut self.output, v.len())
+            .map_err(Error::IoError)?;
+        self.output.write_all(v.as_bytes()).map_err(Error::IoError)
     }
 
-    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::U32_ID)
-            .map_err(Error::IoError)?;
-        self.0.write_u32::<ByteOrder>(v).map_err(Error::IoError)?;
-        Ok(())
+    /// Whether sequences of numbers are packed, which needs version 2.
+    fn packs_sequences(&self) -> bool {
+        self.options.pack_sequences && self.version >= FormatVersion::V2
     }
 
-    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::U64_ID)
-            .map_err(Error::IoError)?;
-        self.0.write_u64::<ByteOrder>(v).map_err(Error::IoError)?;
+    /// Fails if a value needs a data id that was added after the version being written.
+    fn require_version(&self, version: FormatVersion) -> Result<(), Error> {
+        if self.version < version {
+            return Err(Error::InvalidVersion {
+                expected: version.into(),
+                found: self.version.into(),
+            });
+        }
+
         Ok(())
     }
 
-    fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::F32_ID)
-            .map_err(Error::IoError)?;
-        self.0.write_f32::<ByteOrder>(v).map_err(Error::IoError)?;
-        Ok(())
+    /// Starts a container with an unknown length, its contents are buffered until [`Serializer::end_buffered`].
+    fn begin_buffered(&mut self) {
+        self.output.buffers.push(Vec::new());
     }
 
-    fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::F64_ID)
+    /// Writes the length of the innermost buffered container followed by its contents.
+    fn end_buffered(&mut self, len: usize) -> Result<(), Error> {
+        let buffer = self.output.buffers.pop().unwrap_or_default();
+        self.encoding
+            .write_length(&mut self.output, len)
             .map_err(Error::IoError)?;
-        self.0.write_f64::<ByteOrder>(v).map_err(Error::IoError)?;
+        self.output.write_all(&buffer).map_err(Error::IoError)?;
         Ok(())
     }
+}
 
-    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
-        let utf8_bytes = v.to_string().into_bytes();
-        self.0
-            .write_u8(crate::data_ids::CHAR_ID)
-            .map_err(Error::IoError)?;
-        self.0.write(&utf8_bytes).map_err(Error::IoError)?;
-        Ok(())
+impl<W: Write, B: ByteOrder> Drop for Serializer<W, B> {
+    fn drop(&mut self) {
+        // The flate2 encoders finish themselves when dropped, the others need to be finished here.
+        // Errors cannot be reported from here, use `finish` to observe them. The checksum is left out, as
+        // the body may be incomplete.
+        match &mut self.output.writer {
+            #[cfg(feature = "zstd")]
+            Writer::Zstd(w) => {
+                let _ = w.do_finish();
+            }
+            #[cfg(feature = "lz4")]
+            Writer::Lz4(w) => {
+                let _ = w.try_finish();
+            }
+            _ => {}
+        }
     }
+}
 
-    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
-        let bytes = v.as_bytes();
-        self.0
-            .write_u8(crate::data_ids::STR_ID)
-            .map_err(Error::IoError)?;
-        self.0
-            .write_u32::<ByteOrder>(bytes.len() as u32)
-            .map_err(Error::IoError)?;
-        self.0.write(bytes).map_err(Error::IoError)?;
-        Ok(())
+/// Serializes the elements of a sequence or the entries of a map, counting them when the length
+/// was not known up front.
+pub struct Compound<'a, W: Write, B: ByteOrder = BigEndian> {
+    ser: &'a mut Serializer<W, B>,
+    count: Option<usize>,
+    packed: Option<Packed>,
+}
+
+/// The elements of a sequence that have all been the same numeric type so far, stored without their
+/// ids until the sequence ends or an element of another type is serialized.
+struct Packed {
+    /// The id written if the elements cannot be packed.
+    container: u8,
+    /// Only `u8` elements are collected, to be written as bytes. This is the case for tuples and when
+    /// [`SerializerOptions::pack_sequences`] is not set.
+    bytes_only: bool,
+    len: Option<usize>,
+    id: Option<u8>,
+    count: usize,
+    values: Vec<u8>,
+    element: Vec<u8>,
+}
+
+impl Packed {
+    /// Adds a serialized element to the packed values, returning false if it cannot be packed with them.
+    fn push<B: ByteOrder>(&mut self, element: &[u8], encoding: Encoding<B>) -> bool {
+        let Some((&id, value)) = element.split_first() else {
+            return false;
+        };
+
+        if crate::data_ids::packed_width(id).is_none()
+            || encoding.value_len(id, value) != value.len()
+            || (self.bytes_only && id != crate::data_ids::U8_ID)
+            || *self.id.get_or_insert(id) != id
+        {
+            return false;
+        }
+
+        self.values.extend_from_slice(value);
+        self.count += 1;
+        true
     }
 
-    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::BYTES_ID)
-            .map_err(Error::IoError)?;
-        self.0
-            .write_u32::<ByteOrder>(v.len() as u32)
-            .map_err(Error::IoError)?;
-        self.0.write(v).map_err(Error::IoError)?;
-        Ok(())
+    /// Whether a first element of the given type, as returned by [`NumericProbe`], may be packed.
+    fn accepts_first<T: ?Sized + serde::Serialize>(&self, value: &T) -> bool {
+        match value.serialize(NumericProbe) {
+            Ok(id) => !self.bytes_only || id == crate::data_ids::U8_ID,
+            Err(NotNumeric) => false,
+        }
     }
+}
 
-    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::NULL_ID)
-            .map_err(Error::IoError)?;
-        Ok(())
+/// Finds the data id of a numeric value without serializing it, failing as soon as the value turns out
+/// to be anything else so that large values are not visited.
+struct NumericProbe;
+
+#[derive(Debug)]
+struct NotNumeric;
+
+impl fmt::Display for NotNumeric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("not a number")
     }
+}
 
-    fn serialize_some<T: ?Sized + serde::Serialize>(
-        self,
-        value: &T,
-    ) -> Result<Self::Ok, Self::Error> {
-        value.serialize(self)
+impl std::error::Error for NotNumeric {}
+
+impl serde::ser::Error for NotNumeric {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        NotNumeric
     }
+}
 
-    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
-        self.serialize_none()
+impl serde::ser::Serializer for NumericProbe {
+    type Ok = u8;
+    type Error = NotNumeric;
+
+    type SerializeSeq = Impossible<u8, NotNumeric>;
+    type SerializeTuple = Impossible<u8, NotNumeric>;
+    type SerializeTupleStruct = Impossible<u8, NotNumeric>;
+    type SerializeTupleVariant = Impossible<u8, NotNumeric>;
+    type SerializeMap = Impossible<u8, NotNumeric>;
+    type SerializeStruct = Impossible<u8, NotNumeric>;
+    type SerializeStructVariant = Impossible<u8, NotNumeric>;
+
+    fn serialize_bool(self, _v: bool) -> Result<u8, NotNumeric> {
+        Err(NotNumeric)
     }
 
-    fn serialize_unit_struct(self, _: &'static str) -> Result<Self::Ok, Self::Error> {
-        self.serialize_none()
+    fn serialize_i8(self, _v: i8) -> Result<u8, NotNumeric> {
+        Ok(crate::data_ids::I8_ID)
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<u8, NotNumeric> {
+        Ok(crate::data_ids::I16_ID)
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<u8, NotNumeric> {
+        Ok(crate::data_ids::I32_ID)
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<u8, NotNumeric> {
+        Ok(crate::data_ids::I64_ID)
+    }
+
+    fn serialize_i128(self, _v: i128) -> Result<u8, NotNumeric> {
+        Ok(crate::data_ids::I128_ID)
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<u8, NotNumeric> {
+        Ok(crate::data_ids::U8_ID)
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<u8, NotNumeric> {
+        Ok(crate::data_ids::U16_ID)
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<u8, NotNumeric> {
+        Ok(crate::data_ids::U32_ID)
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<u8, NotNumeric> {
+        Ok(crate::data_ids::U64_ID)
+    }
+
+    fn serialize_u128(self, _v: u128) -> Result<u8, NotNumeric> {
+        Ok(crate::data_ids::U128_ID)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<u8, NotNumeric> {
+        Ok(crate::data_ids::F32_ID)
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<u8, NotNumeric> {
+        Ok(crate::data_ids::F64_ID)
+    }
+
+    fn serialize_char(self, _v: char) -> Result<u8, NotNumeric> {
+        Err(NotNumeric)
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<u8, NotNumeric> {
+        Err(NotNumeric)
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<u8, NotNumeric> {
+        Err(NotNumeric)
+    }
+
+    fn serialize_none(self) -> Result<u8, NotNumeric> {
+        Err(NotNumeric)
+    }
+
+    fn serialize_some<T: ?Sized + serde::Serialize>(self, _value: &T) -> Result<u8, NotNumeric> {
+        Err(NotNumeric)
+    }
+
+    fn serialize_unit(self) -> Result<u8, NotNumeric> {
+        Err(NotNumeric)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<u8, NotNumeric> {
+        Err(NotNumeric)
     }
 
     fn serialize_unit_variant(
         self,
         _name: &'static str,
-        variant_index: u32,
+        _variant_index: u32,
         _variant: &'static str,
-    ) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::UNIT_VARIANT_ID)
-            .map_err(Error::IoError)?;
-        self.0
-            .write_u32::<ByteOrder>(variant_index)
-            .map_err(Error::IoError)?;
-        Ok(())
+    ) -> Result<u8, NotNumeric> {
+        Err(NotNumeric)
     }
 
     fn serialize_newtype_struct<T: ?Sized + serde::Serialize>(
         self,
         _name: &'static str,
         value: &T,
-    ) -> Result<Self::Ok, Self::Error> {
+    ) -> Result<u8, NotNumeric> {
+        // Newtype structs are written as their value.
         value.serialize(self)
     }
 
     fn serialize_newtype_variant<T: ?Sized + serde::Serialize>(
         self,
         _name: &'static str,
-        variant_index: u32,
+        _variant_index: u32,
         _variant: &'static str,
-        value: &T,
-    ) -> Result<Self::Ok, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::ENUM_VARIANT_ID)
-            .map_err(Error::IoError)?;
-        self.0
-            .write_u32::<ByteOrder>(variant_index)
-            .map_err(Error::IoError)?;
-        value.serialize(self)
+        _value: &T,
+    ) -> Result<u8, NotNumeric> {
+        Err(NotNumeric)
     }
 
-    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
-        let len = len.ok_or(Error::LengthRequired)?;
-        self.0
-            .write_u8(crate::data_ids::SEQ_ID)
-            .map_err(Error::IoError)?;
-        self.0
-            .write_u32::<ByteOrder>(len as u32)
-            .map_err(Error::IoError)?;
-        Ok(self)
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, NotNumeric> {
+        Err(NotNumeric)
     }
 
-    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::TUPLE_ID)
-            .map_err(Error::IoError)?;
-        self.0
-            .write_u32::<ByteOrder>(len as u32)
-            .map_err(Error::IoError)?;
-        Ok(self)
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, NotNumeric> {
+        Err(NotNumeric)
     }
 
     fn serialize_tuple_struct(
         self,
         _name: &'static str,
-        len: usize,
-    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::TUPLE_STRUCT_ID)
-            .map_err(Error::IoError)?;
-        self.0
-            .write_u32::<ByteOrder>(len as u32)
-            .map_err(Error::IoError)?;
-        Ok(self)
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, NotNumeric> {
+        Err(NotNumeric)
     }
 
     fn serialize_tuple_variant(
         self,
         _name: &'static str,
-        variant_index: u32,
+        _variant_index: u32,
         _variant: &'static str,
-        len: usize,
-    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::ENUM_VARIANT_ID)
-            .map_err(Error::IoError)?;
-        self.0
-            .write_u32::<ByteOrder>(variant_index)
-            .map_err(Error::IoError)?;
-        self.0
-            .write_u32::<ByteOrder>(len as u32)
-            .map_err(Error::IoError)?;
-        Ok(self)
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, NotNumeric> {
+        Err(NotNumeric)
     }
 
-    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        let len = len.ok_or(Error::LengthRequired)?;
-        self.0
-            .write_u8(crate::data_ids::MAP_ID)
-            .map_err(Error::IoError)?;
-        self.0
-            .write_u32::<ByteOrder>(len as u32)
-            .map_err(Error::IoError)?;
-        Ok(self)
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, NotNumeric> {
+        Err(NotNumeric)
     }
 
     fn serialize_struct(
         self,
         _name: &'static str,
-        len: usize,
-    ) -> Result<Self::SerializeStruct, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::MAP_ID)
-            .map_err(Error::IoError)?;
-        self.0
-            .write_u32::<ByteOrder>(len as u32)
-            .map_err(Error::IoError)?;
-        Ok(self)
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, NotNumeric> {
+        Err(NotNumeric)
     }
 
     fn serialize_struct_variant(
         self,
         _name: &'static str,
-        variant_index: u32,
+        _variant_index: u32,
         _variant: &'static str,
-        len: usize,
-    ) -> Result<Self::SerializeStructVariant, Self::Error> {
-        self.0
-            .write_u8(crate::data_ids::ENUM_VARIANT_ID)
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, NotNumeric> {
+        Err(NotNumeric)
+    }
+}
+
+impl<'a, W: Write, B: ByteOrder> Compound<'a, W, B> {
+    fn new(ser: &'a mut Serializer<W, B>, len: Option<usize>) -> Result<Self, Error> {
+        let mut compound = Self {
+            ser,
+            count: None,
+            packed: None,
+        };
+
+        compound.begin(len)?;
+        Ok(compound)
+    }
+
+    /// Creates a sequence that is written packed or as bytes if all of its elements are the same numeric
+    /// type. Nothing is written until that is known.
+    fn new_seq(ser: &'a mut Serializer<W, B>, container: u8, len: Option<usize>) -> Self {
+        let bytes_only = container == crate::data_ids::TUPLE_ID || !ser.packs_sequences();
+        Self {
+            ser,
+            count: None,
+            packed: Some(Packed {
+                container,
+                bytes_only,
+                len,
+                id: None,
+                count: 0,
+                values: Vec::new(),
+                element: Vec::new(),
+            }),
+        }
+    }
+
+    /// Writes the length of the container, or starts buffering its contents if the length is unknown.
+    fn begin(&mut self, len: Option<usize>) -> Result<(), Error> {
+        match len {
+            Some(len) => self
+                .ser
+                .encoding
+                .write_length(&mut self.ser.output, len)
+                .map_err(Error::IoError),
+            None => {
+                self.ser.begin_buffered();
+                self.count = Some(0);
+                Ok(())
+            }
+        }
+    }
+
+    /// Serializes an element of a sequence that may still be packed.
+    fn serialize_packed<T: ?Sized + serde::Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let packed = self.packed.as_ref().unwrap();
+        if packed.count == 0 && !packed.accepts_first(value) {
+            // The sequence cannot be packed, so its first element is written without buffering it.
+            self.unpack()?;
+            self.increment();
+            return value.serialize(&mut *self.ser);
+        }
+
+        // Each element is serialized into a reused buffer so that its id can be inspected.
+        let mut element = std::mem::take(&mut self.packed.as_mut().unwrap().element);
+        element.clear();
+        self.ser.output.buffers.push(element);
+        let result = value.serialize(&mut *self.ser);
+        let element = self.ser.output.buffers.pop().unwrap_or_default();
+        result?;
+
+        let packed = self.packed.as_mut().unwrap();
+        if packed.push(&element, self.ser.encoding) {
+            packed.element = element;
+            return Ok(());
+        }
+
+        // The element does not match the ones before it, so write the sequence in full instead.
+        self.unpack()?;
+        self.increment();
+        self.ser.output.write_all(&element).map_err(Error::IoError)
+    }
+
+    /// Starts writing a sequence that turned out not to be packable in full, including the elements that
+    /// were packed so far.
+    fn unpack(&mut self) -> Result<(), Error> {
+        let packed = self.packed.take().unwrap();
+        self.ser
+            .output
+            .write_u8(packed.container)
             .map_err(Error::IoError)?;
-        self.0
-            .write_u32::<ByteOrder>(variant_index)
+        self.begin(packed.len)?;
+        if let Some(id) = packed.id {
+            let mut values = packed.values.as_slice();
+            while !values.is_empty() {
+                let (value, rest) = values.split_at(self.ser.encoding.value_len(id, values));
+                values = rest;
+                self.increment();
+                self.ser.output.write_u8(id).map_err(Error::IoError)?;
+                self.ser.output.write_all(value).map_err(Error::IoError)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes a sequence whose elements were all packed.
+    fn finish_packed(&mut self, packed: Packed) -> Result<(), Error> {
+        let Some(id) = packed.id else {
+            self.ser
+                .output
+                .write_u8(packed.container)
+                .map_err(Error::IoError)?;
+            return self.begin(Some(0));
+        };
+
+        if id == crate::data_ids::U8_ID && self.ser.options.u8_sequences_as_bytes {
+            return serde::Serializer::serialize_bytes(&mut *self.ser, &packed.values);
+        }
+
+        self.ser
+            .output
+            .write_u8(crate::data_ids::PACKED_SEQ_ID)
             .map_err(Error::IoError)?;
-        self.0
-            .write_u32::<ByteOrder>(len as u32)
+        self.ser.output.write_u8(id).map_err(Error::IoError)?;
+        self.ser
+            .encoding
+            .write_length(&mut self.ser.output, packed.count)
             .map_err(Error::IoError)?;
-        Ok(self)
+        self.ser
+            .output
+            .write_all(&packed.values)
+            .map_err(Error::IoError)
+    }
+
+    fn increment(&mut self) {
+        if let Some(count) = &mut self.count {
+            *count += 1;
+        }
+    }
+
+    fn finish(mut self) -> Result<(), Error> {
+        if let Some(packed) = self.packed.take() {
+            return self.finish_packed(packed);
+        }
+
+        match self.count {
+            Some(count) => self.ser.end_buffered(count),
+            None => Ok(()),
+        }
     }
 }
 
-impl<'a, W: Write> serde::ser::SerializeSeq for &'a mut Serializer<W> {
+impl<'a, W: Write, B: ByteOrder> serde::ser::Serializer for &'a mut Serializer<W, B> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_element<T: ?Sized + serde::Serialize>(
-        &mut self,
-        value: &T,
-    ) -> Result<(), Self::Error> {
-        value.serialize(&mut **self)?;
+    type SerializeSeq = Compound<'a, W, B>;
+    type SerializeTuple = Compound<'a, W, B>;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Compound<'a, W, B>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        self.output
+            .write_u8(crate::data_ids::BOOL_ID)
+            .map_err(Error::IoError)?;
+        self.output
+            .write_u8(if v { 1 } else { 0 })
+            .map_err(Error::IoError)?;
         Ok(())
     }
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        self.output
+            .write_u8(crate::data_ids::I8_ID)
+            .map_err(Error::IoError)?;
+        self.output.write_i8(v).map_err(Error::IoError)?;
         Ok(())
     }
-}
-
-impl<'a, W: Write> serde::ser::SerializeTuple for &'a mut Serializer<W> {
-    type Ok = ();
-    type Error = Error;
 
-    fn serialize_element<T: ?Sized + serde::Serialize>(
-        &mut self,
-        value: &T,
-    ) -> Result<(), Self::Error> {
-        value.serialize(&mut **self)?;
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        self.output
+            .write_u8(crate::data_ids::I16_ID)
+            .map_err(Error::IoError)?;
+        self.encoding
+            .write_i16(&mut self.output, v)
+            .map_err(Error::IoError)?;
         Ok(())
     }
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        self.output
+            .write_u8(crate::data_ids::I32_ID)
+            .map_err(Error::IoError)?;
+        self.encoding
+            .write_i32(&mut self.output, v)
+            .map_err(Error::IoError)?;
         Ok(())
     }
-}
 
-impl<'a, W: Write> serde::ser::SerializeTupleStruct for &'a mut Serializer<W> {
-    type Ok = ();
-    type Error = Error;
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        self.output
+            .write_u8(crate::data_ids::I64_ID)
+            .map_err(Error::IoError)?;
+        self.encoding
+            .write_i64(&mut self.output, v)
+            .map_err(Error::IoError)?;
+        Ok(())
+    }
 
-    fn serialize_field<T: ?Sized + serde::Serialize>(
-        &mut self,
-        value: &T,
-    ) -> Result<(), Self::Error> {
-        value.serialize(&mut **self)?;
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok, Self::Error> {
+        self.require_version(FormatVersion::V2)?;
+        self.output
+            .write_u8(crate::data_ids::I128_ID)
+            .map_err(Error::IoError)?;
+        self.encoding
+            .write_i128(&mut self.output, v)
+            .map_err(Error::IoError)?;
         Ok(())
     }
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        self.output
+            .write_u8(crate::data_ids::U8_ID)
+            .map_err(Error::IoError)?;
+        self.output.write_u8(v).map_err(Error::IoError)?;
         Ok(())
     }
-}
 
-impl<'a, W: Write> serde::ser::SerializeTupleVariant for &'a mut Serializer<W> {
-    type Ok = ();
-    type Error = Error;
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        self.output
+            .write_u8(crate::data_ids::U16_ID)
+            .map_err(Error::IoError)?;
+        self.encoding
+            .write_u16(&mut self.output, v)
+            .map_err(Error::IoError)?;
+        Ok(())
+    }
 
-    fn serialize_field<T: ?Sized + serde::Serialize>(
-        &mut self,
-        value: &T,
-    ) -> Result<(), Self::Error> {
-        value.serialize(&mut **self)?;
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        self.output
+            .write_u8(crate::data_ids::U32_ID)
+            .map_err(Error::IoError)?;
+        self.encoding
+            .write_u32(&mut self.output, v)
+            .map_err(Error::IoError)?;
         Ok(())
     }
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        self.output
+            .write_u8(crate::data_ids::U64_ID)
+            .map_err(Error::IoError)?;
+        self.encoding
+            .write_u64(&mut self.output, v)
+            .map_err(Error::IoError)?;
         Ok(())
     }
-}
 
-impl<'a, W: Write> serde::ser::SerializeMap for &'a mut Serializer<W> {
-    type Ok = ();
-    type Error = Error;
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
+        self.require_version(FormatVersion::V2)?;
+        self.output
+            .write_u8(crate::data_ids::U128_ID)
+            .map_err(Error::IoError)?;
+        self.encoding
+            .write_u128(&mut self.output, v)
+            .map_err(Error::IoError)?;
+        Ok(())
+    }
 
-    fn serialize_key<T: ?Sized + serde::Serialize>(&mut self, key: &T) -> Result<(), Self::Error> {
-        key.serialize(&mut **self)?;
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+        self.output
+            .write_u8(crate::data_ids::F32_ID)
+            .map_err(Error::IoError)?;
+        self.encoding
+            .write_f32(&mut self.output, v)
+            .map_err(Error::IoError)?;
         Ok(())
     }
 
-    fn serialize_value<T: ?Sized + serde::Serialize>(
-        &mut self,
-        value: &T,
-    ) -> Result<(), Self::Error> {
-        value.serialize(&mut **self)?;
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
+        self.output
+            .write_u8(crate::data_ids::F64_ID)
+            .map_err(Error::IoError)?;
+        self.encoding
+            .write_f64(&mut self.output, v)
+            .map_err(Error::IoError)?;
         Ok(())
     }
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        let utf8_bytes = v.to_string().into_bytes();
+        self.output
+            .write_u8(crate::data_ids::CHAR_ID)
+            .map_err(Error::IoError)?;
+        self.output.write(&utf8_bytes).map_err(Error::IoError)?;
         Ok(())
     }
-}
 
-impl<'a, W: Write> serde::ser::SerializeStruct for &'a mut Serializer<W> {
-    type Ok = ();
-    type Error = Error;
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        if std::mem::take(&mut self.key) {
+            return self.serialize_interned(v);
+        }
 
-    fn serialize_field<T: ?Sized + serde::Serialize>(
-        &mut self,
-        key: &'static str,
-        value: &T,
-    ) -> Result<(), Self::Error> {
-        key.serialize(&mut **self)?;
-        value.serialize(&mut **self)?;
+        let bytes = v.as_bytes();
+        self.output
+            .write_u8(crate::data_ids::STR_ID)
+            .map_err(Error::IoError)?;
+        self.encoding
+            .write_length(&mut self.output, bytes.len())
+            .map_err(Error::IoError)?;
+        self.output.write(bytes).map_err(Error::IoError)?;
         Ok(())
     }
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        self.output
+            .write_u8(crate::data_ids::BYTES_ID)
+            .map_err(Error::IoError)?;
+        self.encoding
+            .write_length(&mut self.output, v.len())
+            .map_err(Error::IoError)?;
+        self.output.write(v).map_err(Error::IoError)?;
         Ok(())
     }
-}
 
-impl<'a, W: Write> serde::ser::SerializeStructVariant for &'a mut Serializer<W> {
-    type Ok = ();
-    type Error = Error;
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        self.output
+            .write_u8(crate::data_ids::NULL_ID)
+            .map_err(Error::IoError)?;
+        Ok(())
+    }
 
-    fn serialize_field<T: ?Sized + serde::Serialize>(
-        &mut self,
-        key: &'static str,
+    fn serialize_some<T: ?Sized + serde::Serialize>(
+        self,
         value: &T,
-    ) -> Result<(), Self::Error> {
-        key.serialize(&mut **self)?;
-        value.serialize(&mut **self)?;
-        Ok(())
+    ) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
     }
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        self.serialize_none()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Self::Ok, Self::Error> {
+        self.serialize_none()
+    }
 
-    use serde::Serialize;
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        self.serialize_variant(true, variant_index, variant)
+    }
 
-    use crate::data_ids;
+    fn serialize_newtype_struct<T: ?Sized + serde::Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
 
-    use super::*;
+    fn serialize_newtype_variant<T: ?Sized + serde::Serialize>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        self.serialize_variant(false, variant_index, variant)?;
+        value.serialize(self)
+    }
 
-    fn no_compression_serialization_test<T: serde::Serialize>(value: &T) -> Vec<u8> {
-        let compression = Compression::None;
-        let default_hdr_bytes = FileHeader::new(compression).to_bytes().unwrap();
-        let serialized = to_bytes(value, compression).unwrap();
-        assert!(serialized.len() >= default_hdr_bytes.len());
-        assert_eq!(&serialized[0..8], default_hdr_bytes.as_slice());
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        if len.is_none() && self.options.require_lengths {
+            return Err(Error::LengthRequired);
+        }
 
-        (&serialized[8..]).to_vec()
-    }
+        if self.packs_sequences() || self.options.u8_sequences_as_bytes {
+            return Ok(Compound::new_seq(self, crate::data_ids::SEQ_ID, len));
+        }
 
-    #[test]
-    fn test_bool_serialization() {
-        let test = no_compression_serialization_test(&true);
-        assert_eq!(test.as_slice(), &[data_ids::BOOL_ID, 1]);
-        let test = no_compression_serialization_test(&false);
-        assert_eq!(test.as_slice(), &[data_ids::BOOL_ID, 0]);
+        self.output
+            .write_u8(crate::data_ids::SEQ_ID)
+            .map_err(Error::IoError)?;
+        Compound::new(self, len)
     }
 
-    #[test]
-    fn test_integer_serialization() {
-        let test = no_compression_serialization_test(&1_u8);
-        assert_eq!(test.as_slice(), &[data_ids::U8_ID, 1]);
-        let test = no_compression_serialization_test(&1_u16);
-        assert_eq!(test.as_slice(), &[data_ids::U16_ID, 0, 1]);
-        let test = no_compression_serialization_test(&1_u32);
-        assert_eq!(test.as_slice(), &[data_ids::U32_ID, 0, 0, 0, 1]);
-        let test = no_compression_serialization_test(&1_u64);
-        assert_eq!(test.as_slice(), &[data_ids::U64_ID, 0, 0, 0, 0, 0, 0, 0, 1]);
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        if self.options.u8_sequences_as_bytes {
+            return Ok(Compound::new_seq(
+                self,
+                crate::data_ids::TUPLE_ID,
+                Some(len),
+            ));
+        }
 
-        let test = no_compression_serialization_test(&1_i8);
-        assert_eq!(test.as_slice(), &[data_ids::I8_ID, 1]);
-        let test = no_compression_serialization_test(&1_i16);
-        assert_eq!(test.as_slice(), &[data_ids::I16_ID, 0, 1]);
-        let test = no_compression_serialization_test(&1_i32);
-        assert_eq!(test.as_slice(), &[data_ids::I32_ID, 0, 0, 0, 1]);
+        self.output
+            .write_u8(crate::data_ids::TUPLE_ID)
+            .map_err(Error::IoError)?;
+        Compound::new(self, Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        if name == crate::value::NAMED_VARIANT_TOKEN {
+            // A named variant of a `Value`, whose fields are the name followed by the payload if there is one.
+            self.require_version(FormatVersion::V2)?;
+            let id = match len {
+                1 => crate::data_ids::NAMED_UNIT_VARIANT_ID,
+                _ => crate::data_ids::NAMED_ENUM_VARIANT_ID,
+            };
+            self.output.write_u8(id).map_err(Error::IoError)?;
+            self.key = self.options.intern_keys;
+            return Ok(self);
+        }
+
+        self.output
+            .write_u8(crate::data_ids::TUPLE_STRUCT_ID)
+            .map_err(Error::IoError)?;
+        self.encoding
+            .write_length(&mut self.output, len)
+            .map_err(Error::IoError)?;
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        self.serialize_variant(false, variant_index, variant)?;
+        if self.version >= FormatVersion::V2 {
+            self.output
+                .write_u8(crate::data_ids::TUPLE_ID)
+                .map_err(Error::IoError)?;
+        }
+        self.encoding
+            .write_length(&mut self.output, len)
+            .map_err(Error::IoError)?;
+        Ok(self)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        if len.is_none() && self.options.require_lengths {
+            return Err(Error::LengthRequired);
+        }
+
+        self.output
+            .write_u8(crate::data_ids::MAP_ID)
+            .map_err(Error::IoError)?;
+        Compound::new(self, len)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        let id = match self.options.struct_encoding {
+            StructEncoding::Map => crate::data_ids::MAP_ID,
+            StructEncoding::Positional => crate::data_ids::TUPLE_ID,
+        };
+        self.output.write_u8(id).map_err(Error::IoError)?;
+        self.encoding
+            .write_length(&mut self.output, len)
+            .map_err(Error::IoError)?;
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        self.serialize_variant(false, variant_index, variant)?;
+        if self.version == FormatVersion::V1 {
+            self.encoding
+                .write_length(&mut self.output, len)
+                .map_err(Error::IoError)?;
+            return Ok(self);
+        }
+
+        self.serialize_struct(name, len)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.options.human_readable
+    }
+}
+
+impl<'a, W: Write, B: ByteOrder> serde::ser::SerializeSeq for Compound<'a, W, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + serde::Serialize>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        if self.packed.is_some() {
+            return self.serialize_packed(value);
+        }
+
+        self.increment();
+        value.serialize(&mut *self.ser)?;
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl<'a, W: Write, B: ByteOrder> serde::ser::SerializeTuple for Compound<'a, W, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + serde::Serialize>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        serde::ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl<W: Write, B: ByteOrder> serde::ser::SerializeTupleStruct for &mut Serializer<W, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + serde::Serialize>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        value.serialize(&mut **self)?;
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(())
+    }
+}
+
+impl<W: Write, B: ByteOrder> serde::ser::SerializeTupleVariant for &mut Serializer<W, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + serde::Serialize>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        value.serialize(&mut **self)?;
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(())
+    }
+}
+
+impl<'a, W: Write, B: ByteOrder> serde::ser::SerializeMap for Compound<'a, W, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + serde::Serialize>(&mut self, key: &T) -> Result<(), Self::Error> {
+        self.increment();
+        self.ser.serialize_key(key)
+    }
+
+    fn serialize_value<T: ?Sized + serde::Serialize>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        value.serialize(&mut *self.ser)?;
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        self.finish()
+    }
+}
+
+impl<W: Write, B: ByteOrder> serde::ser::SerializeStruct for &mut Serializer<W, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + serde::Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.serialize_struct_field(key, value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), Self::Error> {
+        self.skip_struct_field(key)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(())
+    }
+}
+
+impl<W: Write, B: ByteOrder> serde::ser::SerializeStructVariant for &mut Serializer<W, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + serde::Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        self.serialize_struct_field(key, value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), Self::Error> {
+        self.skip_struct_field(key)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Serialize;
+
+    use crate::{data_ids, LittleEndian};
+
+    use super::*;
+
+    fn no_compression_serialization_test<T: serde::Serialize>(value: &T) -> Vec<u8> {
+        let compression = Compression::None;
+        let default_hdr_bytes = FileHeader::new(compression).to_bytes().unwrap();
+        let serialized = to_bytes(value, compression).unwrap();
+        assert!(serialized.len() >= default_hdr_bytes.len());
+        assert_eq!(
+            &serialized[..default_hdr_bytes.len()],
+            default_hdr_bytes.as_slice()
+        );
+
+        serialized[default_hdr_bytes.len()..].to_vec()
+    }
+
+    /// Serializes a value without compression using the given options and returns the body.
+    fn options_serialization_test<T: serde::Serialize>(
+        value: &T,
+        options: SerializerOptions,
+    ) -> Result<Vec<u8>, Error> {
+        let mut serializer = Serializer::with_options(Vec::new(), Compression::None, options)?;
+        value.serialize(&mut serializer)?;
+        let serialized = serializer.finish()?;
+        let mut body = serialized.as_slice();
+        crate::read_header(&mut body)?;
+        Ok(body.to_vec())
+    }
+
+    #[test]
+    fn test_bool_serialization() {
+        let test = no_compression_serialization_test(&true);
+        assert_eq!(test.as_slice(), &[data_ids::BOOL_ID, 1]);
+        let test = no_compression_serialization_test(&false);
+        assert_eq!(test.as_slice(), &[data_ids::BOOL_ID, 0]);
+    }
+
+    #[test]
+    fn test_integer_serialization() {
+        let test = no_compression_serialization_test(&1_u8);
+        assert_eq!(test.as_slice(), &[data_ids::U8_ID, 1]);
+        let test = no_compression_serialization_test(&1_u16);
+        assert_eq!(test.as_slice(), &[data_ids::U16_ID, 0, 1]);
+        let test = no_compression_serialization_test(&1_u32);
+        assert_eq!(test.as_slice(), &[data_ids::U32_ID, 0, 0, 0, 1]);
+        let test = no_compression_serialization_test(&1_u64);
+        assert_eq!(test.as_slice(), &[data_ids::U64_ID, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+        let test = no_compression_serialization_test(&1_i8);
+        assert_eq!(test.as_slice(), &[data_ids::I8_ID, 1]);
+        let test = no_compression_serialization_test(&1_i16);
+        assert_eq!(test.as_slice(), &[data_ids::I16_ID, 0, 1]);
+        let test = no_compression_serialization_test(&1_i32);
+        assert_eq!(test.as_slice(), &[data_ids::I32_ID, 0, 0, 0, 1]);
         let test = no_compression_serialization_test(&1_i64);
         assert_eq!(test.as_slice(), &[data_ids::I64_ID, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+        let test = v2_serialization_test(&1_u128);
+        let mut expected = vec![data_ids::U128_ID];
+        expected.extend(1_u128.to_be_bytes());
+        assert_eq!(test, expected);
+        let test = v2_serialization_test(&-1_i128);
+        let mut expected = vec![data_ids::I128_ID];
+        expected.extend([0xFF; 16]);
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn test_float_serialization() {
+        let test = no_compression_serialization_test(&1_f32);
+        assert_eq!(test.as_slice(), &[data_ids::F32_ID, 63, 128, 0, 0]);
+        let test = no_compression_serialization_test(&1_f64);
+        assert_eq!(
+            test.as_slice(),
+            &[data_ids::F64_ID, 63, 240, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_char_serialization() {
+        let test = no_compression_serialization_test(&'a');
+        assert_eq!(test.as_slice(), &[data_ids::CHAR_ID, 97]);
+    }
+
+    #[test]
+    fn test_string_serialization() {
+        let test = no_compression_serialization_test(&"hello world");
+        assert_eq!(
+            test.as_slice(),
+            &[
+                data_ids::STR_ID,
+                0,
+                0,
+                0,
+                11,
+                104,
+                101,
+                108,
+                108,
+                111,
+                32,
+                119,
+                111,
+                114,
+                108,
+                100
+            ]
+        );
+    }
+
+    #[test]
+    fn test_null_serialization() {
+        #[derive(Serialize)]
+        struct UnitStruct;
+
+        let test = no_compression_serialization_test(&Option::<u32>::None);
+        assert_eq!(test.as_slice(), &[data_ids::NULL_ID]);
+        let test = no_compression_serialization_test(&());
+        assert_eq!(test.as_slice(), &[data_ids::NULL_ID]);
+        let test = no_compression_serialization_test(&UnitStruct);
+        assert_eq!(test.as_slice(), &[data_ids::NULL_ID]);
+    }
+
+    #[test]
+    fn test_enum_serialization() {
+        #[derive(Serialize)]
+        enum TestEnum {
+            Unit,
+            NewType(u8),
+            Tuple(u8, u8),
+            Struct { a: u8, b: u8 },
+        }
+
+        let test = no_compression_serialization_test(&TestEnum::Unit);
+        assert_eq!(test.as_slice(), &[data_ids::UNIT_VARIANT_ID, 0, 0, 0, 0]);
+        let test = no_compression_serialization_test(&TestEnum::NewType(1));
+        assert_eq!(
+            test.as_slice(),
+            &[data_ids::ENUM_VARIANT_ID, 0, 0, 0, 1, data_ids::U8_ID, 1]
+        );
+        let test = no_compression_serialization_test(&TestEnum::Tuple(1, 2));
+        assert_eq!(
+            test.as_slice(),
+            &[
+                data_ids::ENUM_VARIANT_ID,
+                0,
+                0,
+                0,
+                2,
+                0,
+                0,
+                0,
+                2,
+                data_ids::U8_ID,
+                1,
+                data_ids::U8_ID,
+                2
+            ]
+        );
+        let test = no_compression_serialization_test(&TestEnum::Struct { a: 1, b: 2 });
+        assert_eq!(
+            test.as_slice(),
+            &[
+                data_ids::ENUM_VARIANT_ID,
+                0,
+                0,
+                0,
+                3, // variant index
+                0,
+                0,
+                0,
+                2, // length
+                data_ids::STR_ID,
+                0,
+                0,
+                0,
+                1,
+                97,
+                data_ids::U8_ID,
+                1, // a
+                data_ids::STR_ID,
+                0,
+                0,
+                0,
+                1,
+                98,
+                data_ids::U8_ID,
+                2 // b
+            ]
+        );
+    }
+
+    /// Serializes a value without compression as version 2 and returns the body.
+    fn v2_serialization_test<T: serde::Serialize>(value: &T) -> Vec<u8> {
+        let options = SerializerOptions {
+            version: Some(FormatVersion::V2),
+            ..SerializerOptions::default()
+        };
+        options_serialization_test(value, options).unwrap()
+    }
+
+    #[test]
+    fn test_nested_variant_serialization() {
+        #[derive(Serialize)]
+        enum TestEnum {
+            Unit,
+            NewType(u8),
+            Tuple(u8, u8),
+            Struct { a: u8, b: u8 },
+        }
+
+        let test = v2_serialization_test(&TestEnum::Unit);
+        assert_eq!(test, no_compression_serialization_test(&TestEnum::Unit));
+        let test = v2_serialization_test(&TestEnum::NewType(1));
+        assert_eq!(
+            test,
+            no_compression_serialization_test(&TestEnum::NewType(1))
+        );
+
+        let test = v2_serialization_test(&TestEnum::Tuple(1, 2));
+        assert_eq!(
+            &test[..10],
+            &[
+                data_ids::ENUM_VARIANT_ID,
+                0,
+                0,
+                0,
+                2,
+                data_ids::TUPLE_ID,
+                0,
+                0,
+                0,
+                2
+            ]
+        );
+        assert_eq!(
+            &test[10..],
+            &no_compression_serialization_test(&TestEnum::Tuple(1, 2))[9..]
+        );
+
+        let test = v2_serialization_test(&TestEnum::Struct { a: 1, b: 2 });
+        assert_eq!(
+            &test[..10],
+            &[
+                data_ids::ENUM_VARIANT_ID,
+                0,
+                0,
+                0,
+                3,
+                data_ids::MAP_ID,
+                0,
+                0,
+                0,
+                2
+            ]
+        );
+        assert_eq!(
+            &test[10..],
+            &no_compression_serialization_test(&TestEnum::Struct { a: 1, b: 2 })[9..]
+        );
+    }
+
+    #[test]
+    fn test_version_1_data_ids() {
+        let v1 = SerializerOptions {
+            version: Some(FormatVersion::V1),
+            ..SerializerOptions::default()
+        };
+        for result in [
+            options_serialization_test(&1_i128, v1.clone()),
+            options_serialization_test(&1_u128, v1.clone()),
+            options_serialization_test(
+                &crate::Value::NamedUnitVariant(String::from("A")),
+                v1.clone(),
+            ),
+        ] {
+            assert!(matches!(
+                result,
+                Err(Error::InvalidVersion {
+                    expected: 2,
+                    found: 1
+                })
+            ));
+        }
+
+        // Sequences are only packed from version 2.
+        let test = options_serialization_test(&vec![1_u8, 2], v1.clone()).unwrap();
+        assert_eq!(test[0], data_ids::SEQ_ID);
+
+        for options in [
+            SerializerOptions {
+                intern_keys: true,
+                ..v1.clone()
+            },
+            SerializerOptions {
+                enum_encoding: EnumEncoding::Name,
+                ..v1
+            },
+        ] {
+            assert!(matches!(
+                Serializer::with_options(Vec::new(), Compression::None, options),
+                Err(Error::InvalidVersion {
+                    expected: 2,
+                    found: 1
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_newtype_struct_serialization() {
+        #[derive(Serialize)]
+        struct NewtypeTest(u8);
+
+        let test = no_compression_serialization_test(&NewtypeTest(1));
+        assert_eq!(test.as_slice(), &[data_ids::U8_ID, 1]);
+    }
+
+    #[test]
+    fn test_tuple_struct_serialization() {
+        #[derive(Serialize)]
+        struct TupleTest(u8, u8);
+
+        let test = no_compression_serialization_test(&TupleTest(1, 2));
+        assert_eq!(
+            test.as_slice(),
+            &[
+                data_ids::TUPLE_STRUCT_ID,
+                0,
+                0,
+                0,
+                2,
+                data_ids::U8_ID,
+                1,
+                data_ids::U8_ID,
+                2
+            ]
+        );
+    }
+
+    #[test]
+    fn test_struct_serialization() {
+        #[derive(Serialize)]
+        struct StructTest {
+            a: u8,
+            b: u8,
+        }
+
+        let test = no_compression_serialization_test(&StructTest { a: 1, b: 2 });
+        assert_eq!(
+            test.as_slice(),
+            &[
+                data_ids::MAP_ID,
+                0,
+                0,
+                0,
+                2, // length
+                data_ids::STR_ID,
+                0,
+                0,
+                0,
+                1,
+                97,
+                data_ids::U8_ID,
+                1, // a
+                data_ids::STR_ID,
+                0,
+                0,
+                0,
+                1,
+                98,
+                data_ids::U8_ID,
+                2 // b
+            ]
+        );
+    }
+
+    #[test]
+    fn test_map_serialization() {
+        let mut map = HashMap::<u8, u8>::new();
+        map.insert(1, 2);
+        map.insert(3, 4);
+
+        let test = no_compression_serialization_test(&map);
+        assert_eq!(&test[..5], &[data_ids::MAP_ID, 0, 0, 0, 2]);
+
+        let mut slices = Vec::new();
+        for i in 0..(test.len() - 5) / 4 {
+            slices.push(&test[5 + i * 4..5 + (i + 1) * 4]);
+        }
+
+        slices.sort_by(|a, b| a[1].cmp(&b[1]));
+        assert_eq!(slices.len(), 2);
+        assert_eq!(slices[0], &[data_ids::U8_ID, 1, data_ids::U8_ID, 2]);
+        assert_eq!(slices[1], &[data_ids::U8_ID, 3, data_ids::U8_ID, 4]);
+    }
+
+    /// Serializes a value without compression as version 2, which packs sequences by default.
+    fn packed_serialization_test<T: serde::Serialize>(value: &T) -> Vec<u8> {
+        let options = SerializerOptions {
+            version: Some(FormatVersion::V2),
+            ..SerializerOptions::default()
+        };
+        options_serialization_test(value, options).unwrap()
     }
 
     #[test]
-    fn test_float_serialization() {
-        let test = no_compression_serialization_test(&1_f32);
-        assert_eq!(test.as_slice(), &[data_ids::F32_ID, 63, 128, 0, 0]);
-        let test = no_compression_serialization_test(&1_f64);
+    fn test_packed_seq_serialization() {
+        let test = packed_serialization_test(&vec![1.5_f32, -2.0]);
+        let mut expected = vec![data_ids::PACKED_SEQ_ID, data_ids::F32_ID, 0, 0, 0, 2];
+        expected.extend(1.5_f32.to_be_bytes());
+        expected.extend((-2.0_f32).to_be_bytes());
+        assert_eq!(test, expected);
+
+        let test = no_compression_serialization_test(&vec![1.5_f32, -2.0]);
+        assert_eq!(
+            &test[..6],
+            &[data_ids::SEQ_ID, 0, 0, 0, 2, data_ids::F32_ID]
+        );
+
+        let test = packed_serialization_test(&Vec::<u8>::new());
+        assert_eq!(test.as_slice(), &[data_ids::SEQ_ID, 0, 0, 0, 0]);
+
+        let test = packed_serialization_test(&vec![
+            crate::Value::U8(1),
+            crate::Value::U8(2),
+            crate::Value::U16(3),
+        ]);
         assert_eq!(
             test.as_slice(),
-            &[data_ids::F64_ID, 63, 240, 0, 0, 0, 0, 0, 0]
+            &[
+                data_ids::SEQ_ID,
+                0,
+                0,
+                0,
+                3,
+                data_ids::U8_ID,
+                1,
+                data_ids::U8_ID,
+                2,
+                data_ids::U16_ID,
+                0,
+                3
+            ]
+        );
+
+        // Sequences whose first element is not a number are written directly, while newtype structs
+        // are written as their value and can be packed.
+        #[derive(Serialize)]
+        struct Meters(f32);
+
+        let test = packed_serialization_test(&vec![Some(1_u8), None]);
+        assert_eq!(
+            test,
+            no_compression_serialization_test(&vec![Some(1_u8), None])
         );
+        let test = packed_serialization_test(&vec![Meters(1.5), Meters(-2.0)]);
+        assert_eq!(test, packed_serialization_test(&vec![1.5_f32, -2.0]));
     }
 
-    #[test]
-    fn test_char_serialization() {
-        let test = no_compression_serialization_test(&'a');
-        assert_eq!(test.as_slice(), &[data_ids::CHAR_ID, 97]);
+    fn bytes_mode_serialization_test<T: serde::Serialize>(value: &T) -> Vec<u8> {
+        let options = SerializerOptions {
+            u8_sequences_as_bytes: true,
+            ..SerializerOptions::default()
+        };
+        options_serialization_test(value, options).unwrap()
     }
 
     #[test]
-    fn test_string_serialization() {
-        let test = no_compression_serialization_test(&"hello world");
+    fn test_u8_sequences_as_bytes_serialization() {
+        let test = bytes_mode_serialization_test(&vec![1_u8, 2, 3]);
+        assert_eq!(test.as_slice(), &[data_ids::BYTES_ID, 0, 0, 0, 3, 1, 2, 3]);
+
+        let test = bytes_mode_serialization_test(&[4_u8, 5]);
+        assert_eq!(test.as_slice(), &[data_ids::BYTES_ID, 0, 0, 0, 2, 4, 5]);
+
+        let test = bytes_mode_serialization_test(&vec![1_u16, 2]);
         assert_eq!(
             test.as_slice(),
             &[
-                data_ids::STR_ID,
+                data_ids::SEQ_ID,
                 0,
                 0,
                 0,
-                11,
-                104,
-                101,
-                108,
-                108,
-                111,
-                32,
-                119,
-                111,
-                114,
-                108,
-                100
+                2,
+                data_ids::U16_ID,
+                0,
+                1,
+                data_ids::U16_ID,
+                0,
+                2
             ]
         );
-    }
 
-    #[test]
-    fn test_null_serialization() {
-        #[derive(Serialize)]
-        struct UnitStruct;
-
-        let test = no_compression_serialization_test(&Option::<u32>::None);
-        assert_eq!(test.as_slice(), &[data_ids::NULL_ID]);
-        let test = no_compression_serialization_test(&());
-        assert_eq!(test.as_slice(), &[data_ids::NULL_ID]);
-        let test = no_compression_serialization_test(&UnitStruct);
-        assert_eq!(test.as_slice(), &[data_ids::NULL_ID]);
+        let test = bytes_mode_serialization_test(&(1_u8, 2_u16));
+        assert_eq!(
+            test.as_slice(),
+            &[
+                data_ids::TUPLE_ID,
+                0,
+                0,
+                0,
+                2,
+                data_ids::U8_ID,
+                1,
+                data_ids::U16_ID,
+                0,
+                2
+            ]
+        );
     }
 
     #[test]
-    fn test_enum_serialization() {
+    fn test_interned_key_serialization() {
         #[derive(Serialize)]
-        enum TestEnum {
-            Unit,
-            NewType(u8),
-            Tuple(u8, u8),
-            Struct { a: u8, b: u8 },
+        struct Item {
+            id: u8,
         }
 
-        let test = no_compression_serialization_test(&TestEnum::Unit);
-        assert_eq!(test.as_slice(), &[data_ids::UNIT_VARIANT_ID, 0, 0, 0, 0]);
-        let test = no_compression_serialization_test(&TestEnum::NewType(1));
-        assert_eq!(
-            test.as_slice(),
-            &[data_ids::ENUM_VARIANT_ID, 0, 0, 0, 1, data_ids::U8_ID, 1]
-        );
-        let test = no_compression_serialization_test(&TestEnum::Tuple(1, 2));
+        let options = SerializerOptions {
+            intern_keys: true,
+            ..SerializerOptions::default()
+        };
+        let test =
+            options_serialization_test(&vec![Item { id: 1 }, Item { id: 2 }], options).unwrap();
+
         assert_eq!(
             test.as_slice(),
             &[
-                data_ids::ENUM_VARIANT_ID,
+                data_ids::SEQ_ID,
                 0,
                 0,
                 0,
                 2,
+                data_ids::MAP_ID,
+                0,
+                0,
+                0,
+                1,
+                data_ids::INTERNED_STR_ID,
                 0,
                 0,
                 0,
                 2,
+                b'i',
+                b'd',
                 data_ids::U8_ID,
                 1,
+                data_ids::MAP_ID,
+                0,
+                0,
+                0,
+                1,
+                data_ids::STR_REF_ID,
+                0,
+                0,
+                0,
+                0,
                 data_ids::U8_ID,
                 2
             ]
         );
-        let test = no_compression_serialization_test(&TestEnum::Struct { a: 1, b: 2 });
+    }
+
+    #[test]
+    fn test_positional_struct_serialization() {
+        #[derive(Serialize)]
+        struct Position {
+            x: u8,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            y: Option<u8>,
+        }
+
+        let serialize = |value: &Position| {
+            let options = SerializerOptions {
+                struct_encoding: StructEncoding::Positional,
+                ..SerializerOptions::default()
+            };
+            options_serialization_test(value, options)
+        };
+
+        let test = serialize(&Position { x: 1, y: Some(2) }).unwrap();
         assert_eq!(
             test.as_slice(),
             &[
-                data_ids::ENUM_VARIANT_ID,
+                data_ids::TUPLE_ID,
                 0,
                 0,
                 0,
-                3, // variant index
+                2,
+                data_ids::U8_ID,
+                1,
+                data_ids::U8_ID,
+                2
+            ]
+        );
+        assert!(matches!(
+            serialize(&Position { x: 1, y: None }),
+            Err(Error::SkippedField("y"))
+        ));
+    }
+
+    #[test]
+    fn test_named_variant_serialization() {
+        #[derive(Serialize)]
+        enum Test {
+            Unit,
+            Newtype(u8),
+        }
+
+        let options = SerializerOptions {
+            enum_encoding: EnumEncoding::Name,
+            ..SerializerOptions::default()
+        };
+        let test = options_serialization_test(&(Test::Unit, Test::Newtype(1)), options).unwrap();
+
+        assert_eq!(
+            test.as_slice(),
+            &[
+                data_ids::TUPLE_ID,
                 0,
                 0,
                 0,
-                2, // length
+                2,
+                data_ids::NAMED_UNIT_VARIANT_ID,
                 data_ids::STR_ID,
                 0,
                 0,
                 0,
-                1,
-                97,
-                data_ids::U8_ID,
-                1, // a
+                4,
+                b'U',
+                b'n',
+                b'i',
+                b't',
+                data_ids::NAMED_ENUM_VARIANT_ID,
                 data_ids::STR_ID,
                 0,
                 0,
                 0,
-                1,
-                98,
+                7,
+                b'N',
+                b'e',
+                b'w',
+                b't',
+                b'y',
+                b'p',
+                b'e',
                 data_ids::U8_ID,
-                2 // b
+                1
             ]
         );
     }
 
     #[test]
-    fn test_newtype_struct_serialization() {
-        #[derive(Serialize)]
-        struct NewtypeTest(u8);
+    fn test_varint_serialization() {
+        let options = SerializerOptions {
+            varint_encoding: true,
+            pack_sequences: true,
+            ..SerializerOptions::default()
+        };
+        let mut serializer =
+            Serializer::with_options(Vec::new(), Compression::None, options).unwrap();
+        (300_u32, -2_i64, vec![1_u16, 200], "a")
+            .serialize(&mut serializer)
+            .unwrap();
+        let test = serializer.finish().unwrap();
+
+        let header = FileHeader::with_encoding(Compression::None, Encoding::<BigEndian>::new(true))
+            .to_bytes()
+            .unwrap();
+        assert_eq!(header[8], 0x01);
+        assert_eq!(&test[..9], header.as_slice());
+        assert_eq!(
+            &test[9..],
+            &[
+                data_ids::TUPLE_ID,
+                4,
+                data_ids::U32_ID,
+                0xAC,
+                0x02,
+                data_ids::I64_ID,
+                3,
+                data_ids::PACKED_SEQ_ID,
+                data_ids::U16_ID,
+                2,
+                1,
+                0xC8,
+                0x01,
+                data_ids::STR_ID,
+                1,
+                b'a'
+            ]
+        );
+    }
 
-        let test = no_compression_serialization_test(&NewtypeTest(1));
-        assert_eq!(test.as_slice(), &[data_ids::U8_ID, 1]);
+    #[test]
+    fn test_little_endian_serialization() {
+        let options = SerializerOptions {
+            pack_sequences: true,
+            ..SerializerOptions::default()
+        };
+        let mut serializer =
+            Serializer::<_, LittleEndian>::with_byte_order(Vec::new(), Compression::None, options)
+                .unwrap();
+        (0x0102_u16, vec![1_u32], 1.0_f32)
+            .serialize(&mut serializer)
+            .unwrap();
+        let test = serializer.finish().unwrap();
+
+        let header =
+            FileHeader::with_encoding(Compression::None, Encoding::<LittleEndian>::new(false))
+                .to_bytes()
+                .unwrap();
+        assert_eq!(header[8], 0x02);
+        assert_eq!(&test[..9], header.as_slice());
+        assert_eq!(
+            &test[9..],
+            &[
+                data_ids::TUPLE_ID,
+                3,
+                0,
+                0,
+                0,
+                data_ids::U16_ID,
+                0x02,
+                0x01,
+                data_ids::PACKED_SEQ_ID,
+                data_ids::U32_ID,
+                1,
+                0,
+                0,
+                0,
+                1,
+                0,
+                0,
+                0,
+                data_ids::F32_ID,
+                0,
+                0,
+                0x80,
+                0x3F
+            ]
+        );
     }
 
     #[test]
-    fn test_tuple_struct_serialization() {
-        #[derive(Serialize)]
-        struct TupleTest(u8, u8);
+    fn test_unknown_length_serialization() {
+        struct Unsized<'a>(&'a [u8]);
 
-        let test = no_compression_serialization_test(&TupleTest(1, 2));
+        impl<'a> Serialize for Unsized<'a> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq(self.0.iter().filter(|_| true))
+            }
+        }
+
+        struct Unknown<'a>(&'a [crate::Value]);
+
+        impl<'a> Serialize for Unknown<'a> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_seq(self.0.iter().filter(|_| true))
+            }
+        }
+
+        let test = no_compression_serialization_test(&Unsized(&[1, 2]));
         assert_eq!(
             test.as_slice(),
             &[
-                data_ids::TUPLE_STRUCT_ID,
+                data_ids::SEQ_ID,
                 0,
                 0,
                 0,
@@ -723,17 +2228,47 @@ mod tests {
                 2
             ]
         );
-    }
+        assert_eq!(test, no_compression_serialization_test(&vec![1_u8, 2]));
+
+        let test = packed_serialization_test(&Unsized(&[1, 2]));
+        assert_eq!(
+            test.as_slice(),
+            &[data_ids::PACKED_SEQ_ID, data_ids::U8_ID, 0, 0, 0, 2, 1, 2]
+        );
+
+        // Elements are buffered once a sequence of unknown length turns out not to be packable.
+        let values = [crate::Value::U8(1), crate::Value::Null];
+        let test = packed_serialization_test(&Unknown(&values));
+        assert_eq!(
+            test.as_slice(),
+            &[
+                data_ids::SEQ_ID,
+                0,
+                0,
+                0,
+                2,
+                data_ids::U8_ID,
+                1,
+                data_ids::NULL_ID
+            ]
+        );
 
-    #[test]
-    fn test_struct_serialization() {
         #[derive(Serialize)]
-        struct StructTest {
-            a: u8,
+        struct Inner {
             b: u8,
         }
 
-        let test = no_compression_serialization_test(&StructTest { a: 1, b: 2 });
+        #[derive(Serialize)]
+        struct Flattened {
+            a: u8,
+            #[serde(flatten)]
+            inner: Inner,
+        }
+
+        let test = no_compression_serialization_test(&Flattened {
+            a: 1,
+            inner: Inner { b: 2 },
+        });
         assert_eq!(
             test.as_slice(),
             &[
@@ -763,30 +2298,65 @@ mod tests {
     }
 
     #[test]
-    fn test_map_serialization() {
-        let mut map = HashMap::<u8, u8>::new();
-        map.insert(1, 2);
-        map.insert(3, 4);
+    fn test_option_serialization() {
+        let test = no_compression_serialization_test(&Option::<u8>::None);
+        assert_eq!(test.as_slice(), &[data_ids::NULL_ID]);
+        let test = no_compression_serialization_test(&Option::<u8>::Some(1));
+        assert_eq!(test.as_slice(), &[data_ids::U8_ID, 1]);
+    }
 
-        let test = no_compression_serialization_test(&map);
-        assert_eq!(&test[..5], &[data_ids::MAP_ID, 0, 0, 0, 2]);
+    /// Accepts writes up to a fixed number of bytes and fails after that.
+    struct LimitedWriter {
+        remaining: usize,
+    }
 
-        let mut slices = Vec::new();
-        for i in 0..(test.len() - 5) / 4 {
-            slices.push(&test[5 + i * 4..5 + (i + 1) * 4]);
+    impl Write for LimitedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if buf.len() > self.remaining {
+                return Err(std::io::ErrorKind::WriteZero.into());
+            }
+
+            self.remaining -= buf.len();
+            Ok(buf.len())
         }
 
-        slices.sort_by(|a, b| a[1].cmp(&b[1]));
-        assert_eq!(slices.len(), 2);
-        assert_eq!(slices[0], &[data_ids::U8_ID, 1, data_ids::U8_ID, 2]);
-        assert_eq!(slices[1], &[data_ids::U8_ID, 3, data_ids::U8_ID, 4]);
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
     }
 
     #[test]
-    fn test_option_serialization() {
-        let test = no_compression_serialization_test(&Option::<u8>::None);
-        assert_eq!(test.as_slice(), &[data_ids::NULL_ID]);
-        let test = no_compression_serialization_test(&Option::<u8>::Some(1));
-        assert_eq!(test.as_slice(), &[data_ids::U8_ID, 1]);
+    fn test_finish() {
+        for compression in [
+            Compression::None,
+            Compression::Deflate(6),
+            Compression::GZip(6),
+            Compression::ZLib(6),
+        ] {
+            let mut serializer = Serializer::new(Vec::new(), compression).unwrap();
+            "Hello World!".serialize(&mut serializer).unwrap();
+            let buffer = serializer.finish().unwrap();
+            assert_eq!(buffer, to_bytes(&"Hello World!", compression).unwrap());
+            assert_eq!(
+                crate::from_slice::<String>(&buffer).unwrap(),
+                "Hello World!"
+            );
+        }
+    }
+
+    #[test]
+    fn test_finish_reports_trailer_errors() {
+        let header_length = FileHeader::new(Compression::GZip(6))
+            .to_bytes()
+            .unwrap()
+            .len();
+        let writer = LimitedWriter {
+            remaining: header_length,
+        };
+
+        assert!(matches!(
+            to_writer(writer, &"Hello World!", Compression::GZip(6)),
+            Err(Error::IoError(_))
+        ));
     }
 }
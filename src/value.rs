@@ -0,0 +1,574 @@
+use std::{fmt, marker::PhantomData};
+
+use serde::{
+    de::{DeserializeOwned, Visitor},
+    ser::{SerializeMap, SerializeSeq, SerializeTuple, SerializeTupleStruct},
+    Deserialize, Serialize,
+};
+
+use crate::{data_ids, from_slice, to_bytes_with, Compression, Error, FormatVersion, SbifOptions};
+
+/// Newtype struct name used by [`Value`] to ask the [`crate::Deserializer`] for the raw data id of
+/// the next value instead of going through `deserialize_any`.
+pub(crate) const VALUE_TOKEN: &str = "$serde_sbif::private::Value";
+
+/// Tuple struct name used by [`Value`] to serialize an enum variant identified by name, whose name is not
+/// known at compile time. The fields are the name followed by the payload of non-unit variants.
+pub(crate) const NAMED_VARIANT_TOKEN: &str = "$serde_sbif::private::NamedVariant";
+
+/// A dynamically typed SBIF value. Each variant corresponds to one of the data ids in the format,
+/// so a value read from a file serializes back into the same bytes when written with the same
+/// [`crate::SerializerOptions`] as the file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    I128(i128),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    U128(u128),
+    F32(f32),
+    F64(f64),
+    Char(char),
+    Str(String),
+    Bytes(Vec<u8>),
+    /// A sequence, read from either a regular or a packed sequence. It is written packed if
+    /// [`crate::SerializerOptions::pack_sequences`] applies and its elements are all the same numeric type.
+    Seq(Vec<Value>),
+    Tuple(Vec<Value>),
+    /// A unit enum variant, identified by its variant index.
+    UnitVariant(u32),
+    /// A non-unit enum variant. Tuple variants hold a [`Value::Tuple`] and struct variants hold a [`Value::Map`].
+    /// Version 1 files store the fields of tuple and struct variants without a tuple or map, so such
+    /// variants cannot be read from them. Reading a variant of a version 1 file whose payload may be such
+    /// fields returns [`Error::UntypedVariant`].
+    EnumVariant(u32, Box<Value>),
+    /// A unit enum variant, identified by its name.
+    NamedUnitVariant(String),
+    /// A non-unit enum variant identified by its name, holding the same values as [`Value::EnumVariant`].
+    NamedEnumVariant(String, Box<Value>),
+    TupleStruct(Vec<Value>),
+    /// A map or struct. Entries are kept in the order they were read.
+    Map(Vec<(Value, Value)>),
+}
+
+/// Converts a value into a [`Value`] by serializing and deserializing it as version 2, which can hold every
+/// value and whose variants can be read without their type.
+pub fn to_value<T: Serialize>(value: &T) -> Result<Value, Error> {
+    let bytes = to_bytes_with(value, &conversion_options())?;
+    from_slice(&bytes)
+}
+
+/// Converts a [`Value`] into a concrete type by serializing and deserializing it as version 2.
+pub fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, Error> {
+    let bytes = to_bytes_with(&value, &conversion_options())?;
+    from_slice(&bytes)
+}
+
+fn conversion_options() -> SbifOptions {
+    SbifOptions::new()
+        .compression(Compression::None)
+        .version(FormatVersion::V2)
+}
+
+impl Serialize for Value {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Null => serializer.serialize_unit(),
+            Self::Bool(v) => serializer.serialize_bool(*v),
+            Self::I8(v) => serializer.serialize_i8(*v),
+            Self::I16(v) => serializer.serialize_i16(*v),
+            Self::I32(v) => serializer.serialize_i32(*v),
+            Self::I64(v) => serializer.serialize_i64(*v),
+            Self::I128(v) => serializer.serialize_i128(*v),
+            Self::U8(v) => serializer.serialize_u8(*v),
+            Self::U16(v) => serializer.serialize_u16(*v),
+            Self::U32(v) => serializer.serialize_u32(*v),
+            Self::U64(v) => serializer.serialize_u64(*v),
+            Self::U128(v) => serializer.serialize_u128(*v),
+            Self::F32(v) => serializer.serialize_f32(*v),
+            Self::F64(v) => serializer.serialize_f64(*v),
+            Self::Char(v) => serializer.serialize_char(*v),
+            Self::Str(v) => serializer.serialize_str(v),
+            Self::Bytes(v) => serializer.serialize_bytes(v),
+            Self::Seq(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Self::Tuple(items) => {
+                let mut tuple = serializer.serialize_tuple(items.len())?;
+                for item in items {
+                    tuple.serialize_element(item)?;
+                }
+                tuple.end()
+            }
+            Self::UnitVariant(variant) => serializer.serialize_unit_variant("", *variant, ""),
+            Self::EnumVariant(variant, value) => {
+                serializer.serialize_newtype_variant("", *variant, "", value)
+            }
+            Self::NamedUnitVariant(variant) => {
+                let mut tuple = serializer.serialize_tuple_struct(NAMED_VARIANT_TOKEN, 1)?;
+                tuple.serialize_field(variant)?;
+                tuple.end()
+            }
+            Self::NamedEnumVariant(variant, value) => {
+                let mut tuple = serializer.serialize_tuple_struct(NAMED_VARIANT_TOKEN, 2)?;
+                tuple.serialize_field(variant)?;
+                tuple.serialize_field(value)?;
+                tuple.end()
+            }
+            Self::TupleStruct(items) => {
+                let mut tuple = serializer.serialize_tuple_struct("", items.len())?;
+                for item in items {
+                    tuple.serialize_field(item)?;
+                }
+                tuple.end()
+            }
+            Self::Map(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_newtype_struct(VALUE_TOKEN, ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any sbif value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i8<E>(self, v: i8) -> Result<Value, E> {
+        Ok(Value::I8(v))
+    }
+
+    fn visit_i16<E>(self, v: i16) -> Result<Value, E> {
+        Ok(Value::I16(v))
+    }
+
+    fn visit_i32<E>(self, v: i32) -> Result<Value, E> {
+        Ok(Value::I32(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::I64(v))
+    }
+
+    fn visit_i128<E>(self, v: i128) -> Result<Value, E> {
+        Ok(Value::I128(v))
+    }
+
+    fn visit_u8<E>(self, v: u8) -> Result<Value, E> {
+        Ok(Value::U8(v))
+    }
+
+    fn visit_u16<E>(self, v: u16) -> Result<Value, E> {
+        Ok(Value::U16(v))
+    }
+
+    fn visit_u32<E>(self, v: u32) -> Result<Value, E> {
+        Ok(Value::U32(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Value, E> {
+        Ok(Value::U64(v))
+    }
+
+    fn visit_u128<E>(self, v: u128) -> Result<Value, E> {
+        Ok(Value::U128(v))
+    }
+
+    fn visit_f32<E>(self, v: f32) -> Result<Value, E> {
+        Ok(Value::F32(v))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Value, E> {
+        Ok(Value::F64(v))
+    }
+
+    fn visit_char<E>(self, v: char) -> Result<Value, E> {
+        Ok(Value::Char(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Value, E> {
+        Ok(Value::Str(v))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Value, E> {
+        Ok(Value::Bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Value, E> {
+        Ok(Value::Bytes(v))
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Value::deserialize(deserializer)
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_newtype_struct<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, seq: A) -> Result<Value, A::Error> {
+        Ok(Value::Seq(Elements::visit(seq)?))
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<Value, A::Error> {
+        Ok(Value::Map(Entries::visit(map)?))
+    }
+
+    /// Only the sbif deserializer produces enums here, with the data id of the value as the variant.
+    fn visit_enum<A: serde::de::EnumAccess<'de>>(self, data: A) -> Result<Value, A::Error> {
+        use serde::de::VariantAccess;
+
+        let (id, variant) = data.variant::<u8>()?;
+        match id {
+            data_ids::NULL_ID => variant.unit_variant().map(|_| Value::Null),
+            data_ids::BOOL_ID => variant.newtype_variant().map(Value::Bool),
+            data_ids::I8_ID => variant.newtype_variant().map(Value::I8),
+            data_ids::I16_ID => variant.newtype_variant().map(Value::I16),
+            data_ids::I32_ID => variant.newtype_variant().map(Value::I32),
+            data_ids::I64_ID => variant.newtype_variant().map(Value::I64),
+            data_ids::I128_ID => variant.newtype_variant().map(Value::I128),
+            data_ids::U8_ID => variant.newtype_variant().map(Value::U8),
+            data_ids::U16_ID => variant.newtype_variant().map(Value::U16),
+            data_ids::U32_ID => variant.newtype_variant().map(Value::U32),
+            data_ids::U64_ID => variant.newtype_variant().map(Value::U64),
+            data_ids::U128_ID => variant.newtype_variant().map(Value::U128),
+            data_ids::F32_ID => variant.newtype_variant().map(Value::F32),
+            data_ids::F64_ID => variant.newtype_variant().map(Value::F64),
+            data_ids::CHAR_ID => variant.newtype_variant().map(Value::Char),
+            data_ids::STR_ID | data_ids::INTERNED_STR_ID | data_ids::STR_REF_ID => {
+                variant.newtype_variant().map(Value::Str)
+            }
+            data_ids::BYTES_ID => variant
+                .newtype_variant::<ByteBuf>()
+                .map(|bytes| Value::Bytes(bytes.0)),
+            data_ids::SEQ_ID | data_ids::PACKED_SEQ_ID => variant
+                .newtype_variant::<Elements>()
+                .map(|items| Value::Seq(items.0)),
+            data_ids::TUPLE_ID => variant
+                .newtype_variant::<Elements>()
+                .map(|items| Value::Tuple(items.0)),
+            data_ids::UNIT_VARIANT_ID => variant.newtype_variant().map(Value::UnitVariant),
+            data_ids::ENUM_VARIANT_ID => {
+                let (index, value) = variant.tuple_variant(2, EnumVariantVisitor(PhantomData))?;
+                Ok(Value::EnumVariant(index, Box::new(value)))
+            }
+            data_ids::NAMED_UNIT_VARIANT_ID => {
+                variant.newtype_variant().map(Value::NamedUnitVariant)
+            }
+            data_ids::NAMED_ENUM_VARIANT_ID => {
+                let (name, value) = variant.tuple_variant(2, EnumVariantVisitor(PhantomData))?;
+                Ok(Value::NamedEnumVariant(name, Box::new(value)))
+            }
+            data_ids::TUPLE_STRUCT_ID => variant
+                .newtype_variant::<Elements>()
+                .map(|items| Value::TupleStruct(items.0)),
+            data_ids::MAP_ID => variant
+                .newtype_variant::<Entries>()
+                .map(|entries| Value::Map(entries.0)),
+            found => Err(serde::de::Error::custom(Error::InvalidDataId {
+                expected: format!("from {} to {}", data_ids::NULL_ID, data_ids::LAST_ID),
+                found,
+            })),
+        }
+    }
+}
+
+/// Visits the variant index or name of an enum variant followed by its payload.
+struct EnumVariantVisitor<K>(PhantomData<K>);
+
+impl<'de, K: Deserialize<'de>> Visitor<'de> for EnumVariantVisitor<K> {
+    type Value = (K, Value);
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a variant followed by a value")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let index = seq
+            .next_element()?
+            .ok_or_else(|| serde::de::Error::invalid_length(0, &self))?;
+        let value = seq
+            .next_element()?
+            .ok_or_else(|| serde::de::Error::invalid_length(1, &self))?;
+        Ok((index, value))
+    }
+}
+
+/// The most elements preallocated for a sequence or map, as the length read from the data has not been
+/// checked against the remaining input.
+const MAX_PREALLOCATED: usize = 4096;
+
+/// The elements of a sequence, tuple or tuple struct.
+struct Elements(Vec<Value>);
+
+impl Elements {
+    fn visit<'de, A: serde::de::SeqAccess<'de>>(mut seq: A) -> Result<Vec<Value>, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(MAX_PREALLOCATED));
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(items)
+    }
+}
+
+impl<'de> Deserialize<'de> for Elements {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ElementsVisitor;
+
+        impl<'de> Visitor<'de> for ElementsVisitor {
+            type Value = Elements;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a sequence")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(self, seq: A) -> Result<Elements, A::Error> {
+                Elements::visit(seq).map(Elements)
+            }
+        }
+
+        deserializer.deserialize_any(ElementsVisitor)
+    }
+}
+
+/// The key value pairs of a map.
+struct Entries(Vec<(Value, Value)>);
+
+impl Entries {
+    fn visit<'de, A: serde::de::MapAccess<'de>>(
+        mut map: A,
+    ) -> Result<Vec<(Value, Value)>, A::Error> {
+        let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0).min(MAX_PREALLOCATED));
+        while let Some(entry) = map.next_entry()? {
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+impl<'de> Deserialize<'de> for Entries {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor;
+
+        impl<'de> Visitor<'de> for EntriesVisitor {
+            type Value = Entries;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<Entries, A::Error> {
+                Entries::visit(map).map(Entries)
+            }
+        }
+
+        deserializer.deserialize_map(EntriesVisitor)
+    }
+}
+
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ByteBufVisitor;
+
+        impl<'de> Visitor<'de> for ByteBufVisitor {
+            type Value = ByteBuf;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a byte array")
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<ByteBuf, E> {
+                Ok(ByteBuf(v))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(ByteBufVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    fn value_round_trip(value: Value) {
+        let bytes = to_bytes_with(&value, &conversion_options()).unwrap();
+        let deserialized: Value = from_slice(&bytes).unwrap();
+        assert_eq!(value, deserialized);
+        assert_eq!(
+            bytes,
+            to_bytes_with(&deserialized, &conversion_options()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_value_round_trip() {
+        value_round_trip(Value::Null);
+        value_round_trip(Value::Bool(true));
+        value_round_trip(Value::I8(-1));
+        value_round_trip(Value::I16(-2));
+        value_round_trip(Value::I32(-3));
+        value_round_trip(Value::I64(-4));
+        value_round_trip(Value::I128(-5));
+        value_round_trip(Value::U8(1));
+        value_round_trip(Value::U16(2));
+        value_round_trip(Value::U32(3));
+        value_round_trip(Value::U64(4));
+        value_round_trip(Value::U128(5));
+        value_round_trip(Value::F32(1.5));
+        value_round_trip(Value::F64(2.5));
+        value_round_trip(Value::Char('🎨'));
+        value_round_trip(Value::Str("Hello World!".to_string()));
+        value_round_trip(Value::Bytes(b"Hello World!".to_vec()));
+        value_round_trip(Value::Seq(vec![Value::U8(1), Value::Null]));
+        value_round_trip(Value::Tuple(vec![Value::U8(1), Value::Char('a')]));
+        value_round_trip(Value::UnitVariant(3));
+        value_round_trip(Value::EnumVariant(1, Box::new(Value::Null)));
+        value_round_trip(Value::NamedUnitVariant(String::from("A")));
+        value_round_trip(Value::NamedEnumVariant(
+            String::from("B"),
+            Box::new(Value::Tuple(vec![Value::U8(1), Value::Null])),
+        ));
+        value_round_trip(Value::TupleStruct(vec![Value::Bool(false)]));
+        value_round_trip(Value::Map(vec![
+            (Value::U8(1), Value::Seq(Vec::new())),
+            (Value::Str("a".to_string()), Value::Map(Vec::new())),
+        ]));
+    }
+
+    #[test]
+    fn test_value_declared_length_exceeds_body() {
+        let mut bytes = crate::to_bytes(&Value::Null, Compression::None).unwrap();
+        bytes.pop();
+        for id in [data_ids::SEQ_ID, data_ids::MAP_ID] {
+            let mut truncated = bytes.clone();
+            truncated.extend([id, 0xFF, 0xFF, 0xFF, 0xFF, data_ids::U8_ID, 1]);
+            assert!(from_slice::<Value>(&truncated).is_err());
+        }
+    }
+
+    #[test]
+    fn test_value_bytes_match_typed_serialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum TestEnum {
+            Unit,
+            Newtype(u8),
+            Tuple(u8, char, String),
+            Struct { a: u8, b: char, c: String },
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct TupleStruct(u8, Option<u16>);
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Struct {
+            unit: TestEnum,
+            newtype: TestEnum,
+            tuple: TestEnum,
+            structure: TestEnum,
+            tuple_struct: TupleStruct,
+            pair: (i64, f32),
+            map: BTreeMap<String, Vec<u8>>,
+        }
+
+        let data = Struct {
+            unit: TestEnum::Unit,
+            newtype: TestEnum::Newtype(1),
+            tuple: TestEnum::Tuple(1, 'a', "Hello".to_string()),
+            structure: TestEnum::Struct {
+                a: 1,
+                b: 'b',
+                c: "World".to_string(),
+            },
+            tuple_struct: TupleStruct(2, None),
+            pair: (-3, 1.5),
+            map: BTreeMap::from([("a".to_string(), vec![1, 2, 3])]),
+        };
+
+        let value = to_value(&data).unwrap();
+        assert_eq!(
+            to_bytes_with(&data, &conversion_options()).unwrap(),
+            to_bytes_with(&value, &conversion_options()).unwrap()
+        );
+        assert_eq!(data, from_value::<Struct>(value).unwrap());
+    }
+
+    #[test]
+    fn test_value_variants() {
+        #[derive(Serialize)]
+        enum TestEnum {
+            Unit,
+            Tuple(u8, u8),
+            Struct { a: u8 },
+        }
+
+        assert_eq!(to_value(&TestEnum::Unit).unwrap(), Value::UnitVariant(0));
+        assert_eq!(
+            to_value(&TestEnum::Tuple(1, 2)).unwrap(),
+            Value::EnumVariant(1, Box::new(Value::Tuple(vec![Value::U8(1), Value::U8(2)])))
+        );
+        assert_eq!(
+            to_value(&TestEnum::Struct { a: 1 }).unwrap(),
+            Value::EnumVariant(
+                2,
+                Box::new(Value::Map(vec![(
+                    Value::Str("a".to_string()),
+                    Value::U8(1)
+                )]))
+            )
+        );
+    }
+}